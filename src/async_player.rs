@@ -0,0 +1,74 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task;
+use crate::error::{PlayerError, Result};
+use crate::player::{Player, PlayerState};
+
+// How often wait_until_empty() looks to see whether the transmission is over
+const DRAIN_POLL_INTERVAL:Duration = Duration::from_millis(50);
+
+// The Player for tokio applications, e.g. a station controller that wants to .await a
+// transmission alongside its network I/O. Each call is run on tokio's blocking thread pool with
+// spawn_blocking(), since decoding a file or waiting out the previous transmission's tail can take
+// a while; the lead and tail delays of a transmission that's underway are waited out on the
+// Player's own thread as usual, so they never hold up the runtime.
+//
+// The Player is shared, so the rest of its API is still there through player().
+#[derive(Clone)]
+pub struct AsyncPlayer {
+    player: Arc<Player>,
+}
+
+impl AsyncPlayer {
+    pub fn new(player: Player) -> AsyncPlayer {
+        AsyncPlayer{player: Arc::new(player)}
+    }
+
+    pub fn from_shared(player: Arc<Player>) -> AsyncPlayer {
+        AsyncPlayer{player}
+    }
+
+    pub fn player(&self) -> &Arc<Player> {
+        &self.player
+    }
+
+    pub async fn queue_audio(&self, audiofile_path: String) -> Result<()> {
+        self.run(move |player| player.queue_audio(audiofile_path)).await
+    }
+
+    pub async fn play(&self) -> Result<()> {
+        self.run(|player| player.play()).await
+    }
+
+    pub async fn pause(&self) -> Result<()> {
+        self.run(|player| player.pause()).await
+    }
+
+    pub async fn stop(&self) -> Result<()> {
+        self.run(|player| player.stop()).await
+    }
+
+    pub async fn state(&self) -> Result<PlayerState> {
+        self.run(|player| player.state()).await
+    }
+
+    // Player::wait_until_empty(), without tying up a blocking thread for the whole transmission:
+    // the transmission thread is waited for here with tokio's timer, and only what's left after
+    // it (reaping it and saying how it ended) is handed over to the Player
+    pub async fn wait_until_empty(&self) -> Result<()> {
+        while !self.player.transmission_is_over() {
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+        self.run(|player| player.wait_until_empty()).await
+    }
+
+    async fn run<T, F>(&self, call: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Player) -> Result<T> + Send + 'static,
+    {
+        let player = Arc::clone(&self.player);
+        // The call only fails to come back if it panicked
+        task::spawn_blocking(move || call(&player)).await.map_err(|_| PlayerError::PlayerExited)?
+    }
+}
@@ -0,0 +1,694 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::io::{Read, Seek};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use rodio::{Decoder, Sink, Source};
+use rodio::buffer::SamplesBuffer;
+use rodio::decoder::DecoderError;
+use rodio::source::{SeekError, TakeDuration, UniformSourceIterator, Zero};
+use serde::Deserialize;
+
+// Audio as it's handed to the sink once it's been through whatever processing is turned on
+pub type AudioSource = Box<dyn Source<Item = f32> + Send>;
+
+// Where the Player sends its audio. This is the part of rodio's Sink that the Player uses, so that
+// something else (e.g. a mock in tests, or a network stream) can stand in for a sound card. Like
+// a Sink, an AudioSink plays what's appended to it in order, and stays paused until play().
+pub trait AudioSink: Send + Sync {
+    fn append(&self, source: AudioSource);
+
+    fn play(&self);
+
+    fn pause(&self);
+
+    fn is_paused(&self) -> bool;
+
+    // Drops everything queued, including whatever's playing
+    fn clear(&self);
+
+    // How many of the appended sources are still to finish playing
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Blocks until everything queued has finished playing
+    fn sleep_until_end(&self);
+
+    fn volume(&self) -> f32;
+
+    fn set_volume(&self, volume: f32);
+
+    // How far into the source that's playing we are
+    fn get_pos(&self) -> Duration;
+
+    fn try_seek(&self, pos: Duration) -> Result<(), SeekError>;
+}
+
+impl AudioSink for Sink {
+    fn append(&self, source: AudioSource) {
+        Sink::append(self, source)
+    }
+
+    fn play(&self) {
+        Sink::play(self)
+    }
+
+    fn pause(&self) {
+        Sink::pause(self)
+    }
+
+    fn is_paused(&self) -> bool {
+        Sink::is_paused(self)
+    }
+
+    fn clear(&self) {
+        Sink::clear(self)
+    }
+
+    fn len(&self) -> usize {
+        Sink::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        Sink::empty(self)
+    }
+
+    fn sleep_until_end(&self) {
+        Sink::sleep_until_end(self)
+    }
+
+    fn volume(&self) -> f32 {
+        Sink::volume(self)
+    }
+
+    fn set_volume(&self, volume: f32) {
+        Sink::set_volume(self, volume)
+    }
+
+    fn get_pos(&self) -> Duration {
+        Sink::get_pos(self)
+    }
+
+    fn try_seek(&self, pos: Duration) -> Result<(), SeekError> {
+        Sink::try_seek(self, pos)
+    }
+}
+
+// Cutoffs for keeping audio within the voice passband (about 300Hz to 3kHz), since anything
+// wider modulates past the channel and splatters onto the neighbouring ones on FM
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Passband {
+    pub lowpass_hz: Option<u32>,
+    pub highpass_hz: Option<u32>,
+}
+
+impl Passband {
+    // The usual speech channel, which is all a narrowband FM or SSB transmitter should be putting
+    // out: no rumble or hum underneath, and nothing above what the channel spacing leaves room for
+    pub const VOICE:Passband = Passband{lowpass_hz: Some(3000), highpass_hz: Some(300)};
+}
+
+// Passbands by name, for config files
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PassbandPreset {
+    // Passband::VOICE
+    Voice,
+}
+
+impl PassbandPreset {
+    pub fn passband(self) -> Passband {
+        match self {
+            PassbandPreset::Voice => Passband::VOICE,
+        }
+    }
+}
+
+// The formats that rodio can decode. Normally it works out which it's been given by trying each
+// in turn, but saying so up front skips that (and gets the right decoder's error back if the
+// audio turns out not to be what it was meant to be).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioFormat {
+    Wav,
+    Mp3,
+    Flac,
+    Vorbis,
+}
+
+impl AudioFormat {
+    pub(crate) fn decoder<R: Read + Seek + Send + Sync + 'static>(self, reader: R) -> Result<Decoder<R>, DecoderError> {
+        match self {
+            AudioFormat::Wav => Decoder::new_wav(reader),
+            AudioFormat::Mp3 => Decoder::new_mp3(reader),
+            AudioFormat::Flac => Decoder::new_flac(reader),
+            AudioFormat::Vorbis => Decoder::new_vorbis(reader),
+        }
+    }
+}
+
+// Converts 'source' to 'sample_rate', keeping its channels as they are. This is the same linear
+// interpolation that rodio does to anything that doesn't match the output device, but doing it
+// here means that everything after it runs at the rate that actually goes out.
+pub fn resample(source: AudioSource, sample_rate: u32) -> AudioSource {
+    if source.sample_rate() == sample_rate {
+        return source;
+    }
+    let channels = source.channels();
+    Box::new(UniformSourceIterator::<_, f32>::new(source, channels, sample_rate))
+}
+
+// Runs 'source' through a low-pass and/or high-pass filter. These are single biquads, so they roll
+// off gently (12dB/octave) rather than cutting off dead at the given frequencies.
+pub fn band_limit(source: AudioSource, passband: Passband) -> AudioSource {
+    let source = match passband.lowpass_hz {
+        Some(hz) => Box::new(source.low_pass(hz)),
+        None => source,
+    };
+    match passband.highpass_hz {
+        Some(hz) => Box::new(source.high_pass(hz)),
+        None => source,
+    }
+}
+
+// Mixes every frame of 'source' down to a single channel, averaging the channels so that the
+// result can't clip. A radio only has the one channel, and without this it depends on the sound
+// card (and driver) what happens to the others: often they're just dropped, which loses anything
+// panned away from the left.
+pub fn downmix(source: AudioSource) -> AudioSource {
+    if source.channels() == 1 {
+        return source;
+    }
+    Box::new(Downmix{source})
+}
+
+struct Downmix {
+    source: AudioSource,
+}
+
+impl Iterator for Downmix {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        // This is at the start of a frame, so this is how many channels it has
+        let channels = self.source.channels();
+        let mut sum = self.source.next()?;
+        for _ in 1..channels {
+            sum += self.source.next().unwrap_or(0.0);
+        }
+        Some(sum / channels as f32)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let channels = self.source.channels().max(1) as usize;
+        let (lower, upper) = self.source.size_hint();
+        (lower / channels, upper.map(|upper| upper.div_ceil(channels)))
+    }
+}
+
+impl Source for Downmix {
+    fn current_frame_len(&self) -> Option<usize> {
+        let channels = self.source.channels().max(1) as usize;
+        self.source.current_frame_len().map(|len| len / channels)
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)
+    }
+}
+
+// Fades 'source' in over the first 'fade_in' and out over the last 'fade_out', so that it doesn't
+// start or stop with a click. Not every source knows how long it is, so the fade out works by
+// holding back the last 'fade_out' of audio until the end turns up; the format of 'source' is
+// assumed not to change along the way.
+pub fn fade(source: AudioSource, fade_in: Duration, fade_out: Duration) -> AudioSource {
+    let source:AudioSource = match fade_in.is_zero() {
+        true => source,
+        false => Box::new(source.fade_in(fade_in)),
+    };
+    if fade_out.is_zero() {
+        return source;
+    }
+
+    let frames = (fade_out.as_secs_f64() * source.sample_rate() as f64) as usize;
+    let len = frames * source.channels() as usize;
+    Box::new(FadeOut{source, held: VecDeque::with_capacity(len + 1), len, ending: None})
+}
+
+struct FadeOut {
+    source: AudioSource,
+    // The last 'len' samples, which are only let go once we know whether they're near the end
+    held: VecDeque<f32>,
+    len: usize,
+    // Once the source has run out, how many samples the fade was spread over
+    ending: Option<usize>,
+}
+
+impl Iterator for FadeOut {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.ending.is_none() {
+            while self.held.len() <= self.len {
+                match self.source.next() {
+                    Some(sample) => self.held.push_back(sample),
+                    None => {
+                        self.ending = Some(self.held.len());
+                        break;
+                    }
+                }
+            }
+        }
+
+        let sample = self.held.pop_front()?;
+        Some(match self.ending {
+            // Whatever's held is all that's left, and goes from full level down to silence
+            Some(total) => sample * (self.held.len() + 1) as f32 / total as f32,
+            None => sample,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.source.size_hint();
+        (lower.saturating_add(self.held.len()), upper.and_then(|upper| upper.checked_add(self.held.len())))
+    }
+}
+
+impl Source for FadeOut {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        // Anything held was from before the seek
+        self.held.clear();
+        self.ending = None;
+        Ok(())
+    }
+}
+
+// The level that files are brought to before they're transmitted, as a fraction of full scale, so
+// that every over goes out at about the same deviation whatever the files were mastered at
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalization {
+    // The loudest sample ends up at this level
+    Peak(f32),
+    // The average (RMS) level ends up here, which tracks perceived loudness better. Files are
+    // never amplified past the point of clipping, so peaky ones may end up quieter than this.
+    Rms(f32),
+}
+
+impl Normalization {
+    pub fn level(self) -> f32 {
+        match self {
+            Normalization::Peak(level) | Normalization::Rms(level) => level,
+        }
+    }
+}
+
+// Applies 'normalization' to 'source'. Working out the gain means looking at every sample before
+// the first one is played, so the whole of 'source' is decoded into memory first: this is only for
+// files, and won't ever return for an endless stream.
+pub fn normalize<S>(source: S, normalization: Normalization) -> AudioSource
+where
+    S: Source<Item = f32>,
+{
+    let channels = source.channels();
+    let sample_rate = source.sample_rate();
+    let samples:Vec<f32> = source.collect();
+
+    let peak = samples.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+    let rms = (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len().max(1) as f32).sqrt();
+    // There's no level to speak of in silence, so leave it be
+    let gain = match normalization {
+        _ if peak == 0.0 => 1.0,
+        Normalization::Peak(level) => level / peak,
+        Normalization::Rms(level) => (level / rms).min(1.0 / peak),
+    };
+
+    Box::new(SamplesBuffer::new(channels, sample_rate, samples).amplify(gain))
+}
+
+// The first 'duration' of 'source', e.g. of a tone. Unlike rodio's take_duration(), this knows
+// how long it is even when 'source' goes on forever (as the generators do), so that it counts
+// towards the length of the transmission.
+pub fn lasting(source: AudioSource, duration: Duration) -> AudioSource {
+    Box::new(Lasting{source: source.take_duration(duration), duration})
+}
+
+struct Lasting {
+    source: TakeDuration<AudioSource>,
+    duration: Duration,
+}
+
+impl Iterator for Lasting {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.source.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.source.size_hint()
+    }
+}
+
+impl Source for Lasting {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    // Shorter if 'source' runs out first
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration().or(Some(self.duration))
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)
+    }
+}
+
+// Soft-limits the peaks of 'source' so that they never go past full scale: anything below
+// 'threshold' is left exactly as it is, and anything above is squashed more and more the louder it
+// is, which keeps transients from over-deviating the transmitter without the harsh (and wide)
+// distortion of hard clipping. It's still distortion, though, so the lower the threshold, the more
+// of the audio gets coloured by it; it's a safety net for the odd peak, not a way of making
+// everything louder. The radio only sees the level after the volume control, so this can't help
+// with a volume turned up past 1.0.
+pub fn limit(source: AudioSource, threshold: f32) -> AudioSource {
+    Box::new(Limit{source, threshold})
+}
+
+struct Limit {
+    source: AudioSource,
+    threshold: f32,
+}
+
+impl Limit {
+    fn limit(&self, sample: f32) -> f32 {
+        let level = sample.abs();
+        if level <= self.threshold {
+            return sample;
+        }
+        // The headroom left above the threshold, which tanh() eases the peaks into. It starts out
+        // with the same slope as the unlimited audio, so there's no kink where the limiting starts.
+        let knee = 1.0 - self.threshold;
+        if knee.is_nan() || knee <= 0.0 {
+            return sample.clamp(-1.0, 1.0);
+        }
+        (self.threshold + knee * ((level - self.threshold) / knee).tanh()).copysign(sample)
+    }
+}
+
+impl Iterator for Limit {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.source.next()?;
+        Some(self.limit(sample))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.source.size_hint()
+    }
+}
+
+impl Source for Limit {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)
+    }
+}
+
+// Mixes a continuous tone into 'source', for as long as 'source' lasts, e.g. a CTCSS tone to open
+// a tone-protected repeater. The rest of the audio is turned down by 'level' to make room for it,
+// so that the two together can't clip.
+//
+// Each source only knows about itself, so 'phase' carries the tone's phase over from one to the
+// next; otherwise the tone would jump at every boundary between them. Sources are queued well
+// before they play, so each one picks the phase up when it starts rather than when it's made,
+// and keeps it up to date as it goes.
+pub fn with_tone(source: AudioSource, frequency_hz: f32, level: f32, phase: Arc<Mutex<f32>>) -> AudioSource {
+    let step = 2.0 * PI * frequency_hz / source.sample_rate() as f32;
+    Box::new(WithTone{source, step, level, phase: None, channel: 0, shared_phase: phase})
+}
+
+// The tone on its own, without end, e.g. a CTCSS tone sent on a sink of its own for as long as the
+// radio is keyed. It starts at 'phase' and keeps that up to date like with_tone().
+pub fn tone(frequency_hz: f32, level: f32, sample_rate: u32, phase: Arc<Mutex<f32>>) -> AudioSource {
+    with_tone(Box::new(Zero::<f32>::new(1, sample_rate)), frequency_hz, level, phase)
+}
+
+struct WithTone {
+    source: AudioSource,
+    // How far the tone moves on per frame, in radians
+    step: f32,
+    level: f32,
+    // None until the first sample
+    phase: Option<f32>,
+    // Which sample of the current frame is next
+    channel: u16,
+    shared_phase: Arc<Mutex<f32>>,
+}
+
+impl Iterator for WithTone {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.source.next()?;
+        let phase = *self.phase.get_or_insert_with(|| *self.shared_phase.lock().unwrap_or_else(PoisonError::into_inner));
+        let tone = phase.sin() * self.level;
+
+        // Every channel of a frame gets the same tone
+        self.channel += 1;
+        if self.channel >= self.source.channels() {
+            self.channel = 0;
+            let next = (phase + self.step) % (2.0 * PI);
+            self.phase = Some(next);
+            *self.shared_phase.lock().unwrap_or_else(PoisonError::into_inner) = next;
+        }
+
+        Some(sample * (1.0 - self.level) + tone)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.source.size_hint()
+    }
+}
+
+impl Source for WithTone {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(channels: u16, sample_rate: u32, samples: Vec<f32>) -> AudioSource {
+        Box::new(SamplesBuffer::new(channels, sample_rate, samples))
+    }
+
+    fn assert_samples_eq(actual: Vec<f32>, expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len(), "{:?} != {:?}", actual, expected);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-6, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn downmix_averages_left_and_right() {
+        let mono = downmix(buffer(2, 8000, vec![1.0, 0.5, -1.0, 0.2, 0.3, 0.3]));
+        assert_eq!(mono.channels(), 1);
+        assert_eq!(mono.sample_rate(), 8000);
+        assert_samples_eq(mono.collect(), &[0.75, -0.4, 0.3]);
+    }
+
+    #[test]
+    fn downmix_leaves_mono_alone() {
+        assert_samples_eq(downmix(buffer(1, 8000, vec![0.1, 0.2])).collect(), &[0.1, 0.2]);
+    }
+
+    #[test]
+    fn fade_out_ramps_down_to_the_end() {
+        let faded = fade(buffer(1, 10, vec![1.0; 6]), Duration::ZERO, Duration::from_millis(400));
+        assert_samples_eq(faded.collect(), &[1.0, 1.0, 1.0, 0.75, 0.5, 0.25]);
+    }
+
+    #[test]
+    fn fade_out_of_something_shorter_than_the_fade() {
+        let faded = fade(buffer(1, 10, vec![1.0; 2]), Duration::ZERO, Duration::from_millis(400));
+        assert_samples_eq(faded.collect(), &[1.0, 0.5]);
+    }
+
+    #[test]
+    fn fade_in_ramps_up_from_silence() {
+        let faded = fade(buffer(1, 10, vec![1.0; 5]), Duration::from_millis(300), Duration::ZERO);
+        let samples:Vec<f32> = faded.collect();
+        assert_eq!(samples[0], 0.0);
+        assert!(samples[1] < samples[2] && samples[2] < 1.0);
+        assert_eq!(samples[4], 1.0);
+    }
+
+    #[test]
+    fn peak_normalization_sets_the_loudest_sample() {
+        let normalized = normalize(buffer(1, 8000, vec![0.1, -0.25, 0.2]), Normalization::Peak(0.5));
+        assert_samples_eq(normalized.collect(), &[0.2, -0.5, 0.4]);
+    }
+
+    #[test]
+    fn rms_normalization_never_clips() {
+        // The RMS is 0.1 (with a peak of 0.5), so bringing it to 0.5 would need a gain of 5
+        let mut samples = vec![0.0; 25];
+        samples[0] = 0.5;
+        let normalized:Vec<f32> = normalize(buffer(1, 8000, samples), Normalization::Rms(0.5)).collect();
+        assert!((normalized[0] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn normalization_leaves_silence_alone() {
+        assert_samples_eq(normalize(buffer(1, 8000, vec![0.0; 3]), Normalization::Peak(0.5)).collect(), &[0.0; 3]);
+    }
+
+    #[test]
+    fn limiter_leaves_quiet_audio_alone() {
+        assert_samples_eq(limit(buffer(1, 8000, vec![0.1, -0.5, 0.8]), 0.8).collect(), &[0.1, -0.5, 0.8]);
+    }
+
+    #[test]
+    fn limiter_squashes_peaks_below_full_scale() {
+        let limited:Vec<f32> = limit(buffer(1, 8000, vec![0.9, 1.0, -1.2, 10.0]), 0.8).collect();
+        assert!(limited.iter().all(|sample| sample.abs() > 0.8 && sample.abs() <= 1.0));
+        // Louder still comes out louder, just not by as much
+        assert!(limited[0] < limited[1] && limited[1] < -limited[2] && -limited[2] < limited[3]);
+        assert!(limited[1] < 1.0 && limited[2] < 0.0);
+    }
+
+    #[test]
+    fn lasting_knows_how_long_it_is() {
+        let tone = lasting(Box::new(rodio::source::SineWave::new(1000.0)), Duration::from_millis(250));
+        assert_eq!(tone.total_duration(), Some(Duration::from_millis(250)));
+        assert_eq!(tone.count(), 12000);
+
+        // Cut short by the source running out
+        let short = lasting(buffer(1, 8000, vec![0.0; 800]), Duration::from_secs(1));
+        assert_eq!(short.total_duration(), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn limiter_at_full_scale_just_clips() {
+        assert_samples_eq(limit(buffer(1, 8000, vec![0.5, 1.5, -2.0]), 1.0).collect(), &[0.5, 1.0, -1.0]);
+    }
+
+    #[test]
+    fn tone_carries_on_from_one_source_to_the_next() {
+        let phase = Arc::new(Mutex::new(0.0));
+        let tone_only = |len| with_tone(buffer(1, 8000, vec![0.0; len]), 100.0, 1.0, Arc::clone(&phase));
+
+        let mut joined:Vec<f32> = tone_only(30).collect();
+        joined.extend(tone_only(50));
+        let whole:Vec<f32> = with_tone(buffer(1, 8000, vec![0.0; 80]), 100.0, 1.0, Arc::new(Mutex::new(0.0))).collect();
+        assert_samples_eq(joined, &whole);
+    }
+
+    #[test]
+    fn tone_carries_on_into_sources_made_before_it_got_there() {
+        // Both queued up before either plays, as the Player does
+        let phase = Arc::new(Mutex::new(0.0));
+        let first = with_tone(buffer(1, 8000, vec![0.0; 30]), 100.0, 1.0, Arc::clone(&phase));
+        let second = with_tone(buffer(1, 8000, vec![0.0; 50]), 100.0, 1.0, Arc::clone(&phase));
+
+        let joined:Vec<f32> = first.chain(second).collect();
+        let whole:Vec<f32> = with_tone(buffer(1, 8000, vec![0.0; 80]), 100.0, 1.0, Arc::new(Mutex::new(0.0))).collect();
+        assert_samples_eq(joined, &whole);
+    }
+
+    #[test]
+    fn tone_on_its_own_is_the_tone_that_would_be_mixed_in() {
+        let alone:Vec<f32> = tone(100.0, 0.25, 8000, Arc::new(Mutex::new(0.0))).take(80).collect();
+        let mixed:Vec<f32> = with_tone(buffer(1, 8000, vec![0.0; 80]), 100.0, 0.25, Arc::new(Mutex::new(0.0))).collect();
+        assert_samples_eq(alone, &mixed);
+    }
+
+    #[test]
+    fn tone_makes_room_in_the_audio() {
+        let mixed:Vec<f32> = with_tone(buffer(2, 8000, vec![1.0, 1.0]), 100.0, 0.25, Arc::new(Mutex::new(0.0))).collect();
+        // Both channels of the first frame get the tone at phase 0, which is silent
+        assert_samples_eq(mixed, &[0.75, 0.75]);
+    }
+
+    #[test]
+    fn resample_keeps_the_duration() {
+        let resampled = resample(buffer(2, 44100, vec![0.5; 88200]), 48000);
+        assert_eq!((resampled.channels(), resampled.sample_rate()), (2, 48000));
+        assert_eq!(resampled.count(), 96000);
+    }
+}
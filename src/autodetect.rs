@@ -0,0 +1,94 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+
+// This goes by what Linux exposes in sysfs, so it won't find anything on other platforms.
+
+// A Digirig found on the USB bus. It's a USB hub with a CM108 sound card and a CP2102 serial
+// adapter hanging off it, so the two halves are paired up by looking for a sound card and a
+// serial port that share a parent hub.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Digirig {
+    pub audio_device: String,
+    pub tty_path: String,
+    pub serial_number: Option<String>,
+}
+
+// ALSA names the Digirig's sound card "Device", and then "Device_1" etc. if there's more than one
+fn is_digirig_card(card_id: &str) -> bool {
+    card_id == "Device" || card_id.strip_prefix("Device_").is_some_and(|n| n.parse::<u32>().is_ok())
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+// The sysfs directory of the USB device (rather than interface) that 'path' belongs to, which is
+// the nearest ancestor with a serial number or product ID
+fn usb_device(path: &Path) -> Option<&Path> {
+    path.ancestors().find(|p| p.join("idProduct").exists())
+}
+
+pub fn find_digirigs() -> Result<Vec<Digirig>> {
+    // Hub each Digirig sound card is attached to, and its ALSA card ID
+    let mut cards:Vec<(PathBuf, String)> = Vec::new();
+    for card in fs::read_dir("/sys/class/sound").context("Failed to list sound cards")?.flatten() {
+        let Some(id) = read_trimmed(&card.path().join("id")) else {
+            continue;
+        };
+        let Ok(device) = fs::canonicalize(card.path().join("device")) else {
+            continue;
+        };
+        if let Some(hub) = usb_device(&device).and_then(Path::parent) {
+            if is_digirig_card(&id) {
+                cards.push((hub.to_path_buf(), id));
+            }
+        }
+    }
+
+    let mut digirigs:Vec<Digirig> = Vec::new();
+    for tty in fs::read_dir("/sys/class/tty").context("Failed to list serial ports")?.flatten() {
+        let name = tty.file_name().to_string_lossy().into_owned();
+        if !name.starts_with("ttyUSB") {
+            continue;
+        }
+        let Ok(device) = fs::canonicalize(tty.path().join("device")) else {
+            continue;
+        };
+
+        for (hub, id) in &cards {
+            if device.starts_with(hub) {
+                digirigs.push(Digirig{
+                    audio_device: format!("front:CARD={},DEV=0", id),
+                    tty_path: format!("/dev/{}", name),
+                    serial_number: usb_device(&device).and_then(|usb| read_trimmed(&usb.join("serial"))),
+                });
+            }
+        }
+    }
+    digirigs.sort_by(|a, b| a.tty_path.cmp(&b.tty_path));
+
+    Ok(digirigs)
+}
+
+// Finds the one Digirig that's plugged in, or the one with the given serial number (of its
+// serial adapter) if there are several
+pub fn find_digirig(serial_number: Option<&str>) -> Result<Digirig> {
+    let mut digirigs = find_digirigs()?;
+    if let Some(serial_number) = serial_number {
+        digirigs.retain(|d| d.serial_number.as_deref() == Some(serial_number));
+    }
+
+    match digirigs.len() {
+        0 => Err(anyhow!("No Digirig found{}", match serial_number {
+            Some(serial_number) => format!(" with serial number '{}'", serial_number),
+            None => String::new(),
+        })),
+        1 => Ok(digirigs.remove(0)),
+        _ => Err(anyhow!("Found several Digirigs, pick one by serial number:\n  {}", digirigs.iter()
+            .map(|d| format!("{} ({} / {})", d.serial_number.as_deref().unwrap_or("no serial number"),
+                             d.tty_path, d.audio_device))
+            .collect::<Vec<_>>()
+            .join("\n  "))),
+    }
+}
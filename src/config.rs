@@ -0,0 +1,365 @@
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use crate::audio::{Normalization, Passband, PassbandPreset};
+use crate::morse;
+use crate::player::{AutoIdConfig, Player, PlayerBuilder, RogerBeep};
+use crate::ptt::PttLine;
+
+// Anything longer than this is almost certainly a units mix-up (seconds given as milliseconds)
+const MAX_PTT_DELAY_MS:u64 = 10_000;
+
+// The standard CTCSS tones run from 67.0Hz to 254.1Hz, give or take a few non-standard ones
+const CTCSS_RANGE_HZ:RangeInclusive<f32> = 60.0..=260.0;
+
+// The automatic ID, unless the profile says otherwise: every ten minutes (as the FCC asks for) at
+// a speed most people can copy
+const DEFAULT_ID_INTERVAL_MINS:u64 = 10;
+const DEFAULT_ID_WPM:u32 = 20;
+const ID_WPM_RANGE:RangeInclusive<u32> = 5..=60;
+
+// A station profile, e.g.
+//
+//   tty = "/dev/ttyUSB0"
+//   audio_device = "front:CARD=Device,DEV=0"
+//   ptt_line = "rts"
+//   lead_ms = 600
+//   tail_ms = 100
+//   hang_ms = 2000
+//   volume = 0.4
+//   max_volume = 0.6
+//   normalize = { rms = 0.2 }
+//   limiter = 0.8
+//   downmix = true
+//   fade_in_ms = 20
+//   fade_out_ms = 50
+//   audio_filter = "voice"      # 300Hz to 3kHz, or give either cutoff on its own:
+//   audio_highpass_hz = 300
+//   audio_lowpass_hz = 3000
+//   ctcss_hz = 100.0
+//   file_gap_ms = 500
+//   roger_beep = true
+//   max_transmit_secs = 180
+//   callsign = "N0CALL"         # sent in Morse code as the automatic ID
+//   id_interval_mins = 10
+//   id_wpm = 20
+//   transmission_log = "/var/log/rplayer/overs.jsonl"
+//   dry_run = false             # true plays the audio without keying the radio
+//   tts_command = ["espeak-ng", "-w", "{output}", "{text}"]   # with the tts feature
+//
+//   [mqtt]   # with the mqtt feature, see mqtt::MqttConfig
+//   host = "broker.local"
+//
+// Every field is optional so that command line arguments can fill in (or override) whatever the
+// file leaves out; the devices just have to come from somewhere by the time a Player is built.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub tty: Option<String>,
+    pub audio_device: Option<String>,
+    pub ptt_line: Option<PttLine>,
+    pub invert_ptt: Option<bool>,
+    pub lead_ms: Option<u64>,
+    pub tail_ms: Option<u64>,
+    pub hang_ms: Option<u64>,
+    pub volume: Option<f32>,
+    pub max_volume: Option<f32>,
+    pub normalize: Option<Normalization>,
+    pub limiter: Option<f32>,
+    pub downmix: Option<bool>,
+    pub fade_in_ms: Option<u64>,
+    pub fade_out_ms: Option<u64>,
+    pub audio_filter: Option<PassbandPreset>,
+    // These override the cutoffs from 'audio_filter'
+    pub audio_lowpass_hz: Option<u32>,
+    pub audio_highpass_hz: Option<u32>,
+    pub ctcss_hz: Option<f32>,
+    pub file_gap_ms: Option<u64>,
+    pub roger_beep: Option<bool>,
+    pub max_transmit_secs: Option<u64>,
+    pub callsign: Option<String>,
+    pub id_interval_mins: Option<u64>,
+    pub id_wpm: Option<u32>,
+    pub transmission_log: Option<PathBuf>,
+    pub dry_run: Option<bool>,
+    #[cfg(feature = "tts")]
+    pub tts_command: Option<crate::tts::TtsEngine>,
+    #[cfg(feature = "mqtt")]
+    pub mqtt: Option<crate::mqtt::MqttConfig>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file '{}'", path.display()))?;
+        let config:Config = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file '{}'", path.display()))?;
+        config.validate()
+            .with_context(|| format!("Invalid config file '{}'", path.display()))?;
+
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let delays = [("lead_ms", self.lead_ms), ("tail_ms", self.tail_ms), ("hang_ms", self.hang_ms),
+                      ("fade_in_ms", self.fade_in_ms), ("fade_out_ms", self.fade_out_ms),
+                      ("file_gap_ms", self.file_gap_ms)];
+        for (name, delay) in delays {
+            match delay {
+                Some(ms) if ms > MAX_PTT_DELAY_MS => {
+                    return Err(anyhow!("{} must be at most {}ms, not {}ms", name, MAX_PTT_DELAY_MS, ms));
+                },
+                _ => {},
+            }
+        }
+        for (name, volume) in [("volume", self.volume), ("max_volume", self.max_volume)] {
+            match volume {
+                Some(volume) if !volume.is_finite() || volume < 0.0 => {
+                    return Err(anyhow!("{} must be a non-negative number, not {}", name, volume));
+                },
+                _ => {},
+            }
+        }
+        if let (Some(volume), Some(max_volume)) = (self.volume, self.max_volume) {
+            if volume > max_volume {
+                return Err(anyhow!("volume ({}) is above max_volume ({})", volume, max_volume));
+            }
+        }
+        if self.audio_lowpass_hz == Some(0) || self.audio_highpass_hz == Some(0) {
+            return Err(anyhow!("Filter cutoffs must be above 0Hz"));
+        }
+        let passband = self.passband();
+        if let (Some(lowpass), Some(highpass)) = (passband.lowpass_hz, passband.highpass_hz) {
+            if highpass >= lowpass {
+                return Err(anyhow!("audio_highpass_hz ({}Hz) must be below audio_lowpass_hz ({}Hz), or nothing gets through",
+                                   highpass, lowpass));
+            }
+        }
+        if let Some(hz) = self.ctcss_hz {
+            if !CTCSS_RANGE_HZ.contains(&hz) {
+                return Err(anyhow!("ctcss_hz must be a CTCSS tone between {} and {}Hz, not {}Hz",
+                                   CTCSS_RANGE_HZ.start(), CTCSS_RANGE_HZ.end(), hz));
+            }
+        }
+        if let Some(normalize) = self.normalize {
+            let level = normalize.level();
+            if !(level > 0.0 && level <= 1.0) {
+                return Err(anyhow!("normalize level must be between 0 and 1, not {}", level));
+            }
+        }
+        #[cfg(feature = "tts")]
+        if self.tts_command.as_ref().is_some_and(|engine| engine.command.is_empty()) {
+            return Err(anyhow!("tts_command must at least name the program to run"));
+        }
+        if let Some(threshold) = self.limiter {
+            if !(threshold > 0.0 && threshold <= 1.0) {
+                return Err(anyhow!("limiter threshold must be between 0 and 1, not {}", threshold));
+            }
+        }
+        if self.max_transmit_secs == Some(0) {
+            return Err(anyhow!("max_transmit_secs must be above 0, or left out for no limit"));
+        }
+        match &self.callsign {
+            Some(callsign) if callsign.trim().is_empty() => {
+                return Err(anyhow!("callsign can't be empty"));
+            },
+            Some(callsign) => {
+                morse::encode(callsign).with_context(|| format!("callsign '{}' can't be sent in Morse code", callsign))?;
+            },
+            None if self.id_interval_mins.is_some() || self.id_wpm.is_some() => {
+                return Err(anyhow!("id_interval_mins and id_wpm need a callsign to send"));
+            },
+            None => {},
+        }
+        if self.id_interval_mins == Some(0) {
+            return Err(anyhow!("id_interval_mins must be above 0"));
+        }
+        if let Some(wpm) = self.id_wpm {
+            if !ID_WPM_RANGE.contains(&wpm) {
+                return Err(anyhow!("id_wpm must be between {} and {}, not {}", ID_WPM_RANGE.start(), ID_WPM_RANGE.end(), wpm));
+            }
+        }
+
+        Ok(())
+    }
+
+    // The preset's passband, with whichever cutoffs were given on their own instead
+    pub fn passband(&self) -> Passband {
+        let preset = self.audio_filter.map(PassbandPreset::passband).unwrap_or_default();
+        Passband{
+            lowpass_hz: self.audio_lowpass_hz.or(preset.lowpass_hz),
+            highpass_hz: self.audio_highpass_hz.or(preset.highpass_hz),
+        }
+    }
+
+    // A PlayerBuilder with everything from the config applied
+    pub fn builder(&self) -> Result<PlayerBuilder> {
+        self.validate()?;
+        let dry_run = self.dry_run.unwrap_or(false);
+        let audio_device = self.audio_device.clone().context("No audio_device given in config")?;
+
+        let mut builder = Player::builder()
+            .audio_device(audio_device)
+            .dry_run(dry_run);
+        match self.tty.clone() {
+            Some(tty) => builder = builder.tty_path(tty),
+            // A dry run can do without the radio's tty
+            None if dry_run => {},
+            None => return Err(anyhow!("No tty given in config")),
+        }
+        if let Some(ptt_line) = self.ptt_line {
+            builder = builder.ptt_line(ptt_line);
+        }
+        if let Some(invert) = self.invert_ptt {
+            builder = builder.invert_ptt(invert);
+        }
+        if let Some(ms) = self.lead_ms {
+            builder = builder.lead_delay(Duration::from_millis(ms));
+        }
+        if let Some(ms) = self.tail_ms {
+            builder = builder.tail_delay(Duration::from_millis(ms));
+        }
+        if let Some(ms) = self.hang_ms {
+            builder = builder.hang_time(Duration::from_millis(ms));
+        }
+        if let Some(volume) = self.volume {
+            builder = builder.volume(volume);
+        }
+        if let Some(max_volume) = self.max_volume {
+            builder = builder.max_volume(max_volume);
+        }
+        // Left out, these follow the delays
+        if let Some(ms) = self.fade_in_ms {
+            builder = builder.fade_in(Duration::from_millis(ms));
+        }
+        if let Some(ms) = self.fade_out_ms {
+            builder = builder.fade_out(Duration::from_millis(ms));
+        }
+        builder = builder
+            .passband(self.passband())
+            .downmix(self.downmix)
+            .normalization(self.normalize)
+            .limiter(self.limiter)
+            .ctcss(self.ctcss_hz);
+
+        if let Some(ms) = self.file_gap_ms {
+            builder = builder.file_gap(Duration::from_millis(ms));
+        }
+        if self.roger_beep == Some(true) {
+            builder = builder.roger_beep(Some(RogerBeep::default()));
+        }
+        builder = builder
+            .max_transmit(self.max_transmit_secs.map(Duration::from_secs))
+            .auto_id(self.callsign.clone().map(|callsign| AutoIdConfig{
+                callsign,
+                interval: Duration::from_secs(self.id_interval_mins.unwrap_or(DEFAULT_ID_INTERVAL_MINS) * 60),
+                wpm: self.id_wpm.unwrap_or(DEFAULT_ID_WPM),
+            }));
+
+        if let Some(path) = &self.transmission_log {
+            builder = builder.transmission_log(path.clone());
+        }
+        #[cfg(feature = "tts")]
+        if let Some(engine) = &self.tts_command {
+            builder = builder.tts_engine(engine.clone());
+        }
+
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn the_example_profile_is_valid() {
+        let config = parse(r#"
+            tty = "/dev/ttyUSB0"
+            audio_device = "front:CARD=Device,DEV=0"
+            ptt_line = "rts"
+            lead_ms = 600
+            tail_ms = 100
+            hang_ms = 2000
+            volume = 0.4
+            max_volume = 0.6
+            normalize = { rms = 0.2 }
+            limiter = 0.8
+            downmix = true
+            fade_in_ms = 20
+            fade_out_ms = 50
+            audio_highpass_hz = 300
+            audio_lowpass_hz = 3000
+            ctcss_hz = 100.0
+            file_gap_ms = 500
+            roger_beep = true
+            max_transmit_secs = 180
+            callsign = "N0CALL"
+            id_interval_mins = 10
+            id_wpm = 20
+        "#);
+        config.validate().unwrap();
+        assert_eq!(config.ptt_line, Some(PttLine::Rts));
+        assert_eq!(config.normalize, Some(Normalization::Rms(0.2)));
+    }
+
+    #[test]
+    fn everything_is_optional() {
+        assert_eq!(parse(""), Config::default());
+        Config::default().validate().unwrap();
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(toml::from_str::<Config>("led_ms = 600").is_err());
+        assert!(toml::from_str::<Config>("audio_filter = \"wideband\"").is_err());
+    }
+
+    #[test]
+    fn out_of_range_settings_are_rejected() {
+        for toml in [
+            "lead_ms = 600000",
+            "fade_out_ms = 600000",
+            "hang_ms = 600000",
+            "volume = -1.0",
+            "volume = 0.8\nmax_volume = 0.5",
+            "audio_lowpass_hz = 0",
+            "audio_highpass_hz = 3000\naudio_lowpass_hz = 300",
+            "audio_filter = \"voice\"\naudio_highpass_hz = 3500",
+            "ctcss_hz = 1000.0",
+            "normalize = { peak = 1.5 }",
+            "limiter = 0.0",
+            "max_transmit_secs = 0",
+            "callsign = \"\"",
+            "callsign = \"N0CALL~\"",
+            "callsign = \"N0CALL\"\nid_wpm = 500",
+            "callsign = \"N0CALL\"\nid_interval_mins = 0",
+            "id_interval_mins = 10",
+        ] {
+            assert!(parse(toml).validate().is_err(), "{}", toml);
+        }
+    }
+
+    #[test]
+    fn cutoffs_override_the_audio_filter() {
+        assert_eq!(parse("audio_filter = \"voice\"").passband(), Passband::VOICE);
+        assert_eq!(parse("audio_filter = \"voice\"\naudio_lowpass_hz = 2500").passband(),
+                   Passband{lowpass_hz: Some(2500), highpass_hz: Some(300)});
+        assert_eq!(parse("audio_highpass_hz = 200").passband(), Passband{lowpass_hz: None, highpass_hz: Some(200)});
+    }
+
+    #[test]
+    fn builder_needs_the_devices() {
+        assert!(Config::default().builder().is_err());
+        assert!(parse("audio_device = \"default\"").builder().is_err());
+        // But there's no radio to key on a dry run
+        parse("audio_device = \"default\"\ndry_run = true").builder().unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+use std::f32::consts::PI;
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use rodio::buffer::SamplesBuffer;
+
+const SAMPLE_RATE:u32 = 48000;
+
+// Each of the pair of tones gets this, so that together they peak at the same level as the other
+// generated tones and still leave headroom for the volume
+const AMPLITUDE:f32 = 0.25;
+
+// Like Morse code, each tone is ramped on and off so that it doesn't click
+const RAMP_SECS:f32 = 0.003;
+
+// The standard keypad: each key is one tone from its row and one from its column
+const ROWS_HZ:[f32; 4] = [697.0, 770.0, 852.0, 941.0];
+const COLUMNS_HZ:[f32; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+const KEYPAD:[[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D'],
+];
+
+fn tones_for(c: char) -> Option<(f32, f32)> {
+    let c = c.to_ascii_uppercase();
+    KEYPAD.iter().enumerate().find_map(|(row, keys)| {
+        keys.iter().position(|&key| key == c).map(|column| (ROWS_HZ[row], COLUMNS_HZ[column]))
+    })
+}
+
+// Synthesizes 'digits' (0-9, A-D, * and #) as DTMF, each sent for 'tone' with 'gap' of silence
+// after it. Nothing is generated if any of the digits isn't on the keypad.
+pub fn dtmf_source(digits: &str, tone: Duration, gap: Duration) -> Result<SamplesBuffer<f32>> {
+    let pairs = digits.chars()
+        .map(|c| tones_for(c).ok_or_else(|| anyhow!("'{}' isn't a DTMF digit", c)))
+        .collect::<Result<Vec<_>>>()?;
+    if pairs.is_empty() {
+        return Err(anyhow!("No DTMF digits to send"));
+    }
+
+    let tone_samples = (SAMPLE_RATE as f32 * tone.as_secs_f32()) as usize;
+    let gap_samples = (SAMPLE_RATE as f32 * gap.as_secs_f32()) as usize;
+    let ramp_samples = ((SAMPLE_RATE as f32 * RAMP_SECS) as usize).min(tone_samples / 2);
+
+    let mut samples:Vec<f32> = Vec::new();
+    for (row_hz, column_hz) in pairs {
+        for i in 0..tone_samples {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let edge = i.min(tone_samples - 1 - i);
+            let envelope = if edge < ramp_samples {
+                0.5 - 0.5 * (PI * edge as f32 / ramp_samples as f32).cos()
+            } else {
+                1.0
+            };
+            samples.push(AMPLITUDE * envelope * ((2.0 * PI * row_hz * t).sin() + (2.0 * PI * column_hz * t).sin()));
+        }
+        samples.resize(samples.len() + gap_samples, 0.0);
+    }
+
+    Ok(SamplesBuffer::new(1, SAMPLE_RATE, samples))
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::Source;
+    use super::*;
+
+    #[test]
+    fn digits_have_a_row_and_a_column() {
+        assert_eq!(tones_for('1'), Some((697.0, 1209.0)));
+        assert_eq!(tones_for('0'), Some((941.0, 1336.0)));
+        assert_eq!(tones_for('#'), Some((941.0, 1477.0)));
+        assert_eq!(tones_for('d'), Some((941.0, 1633.0)));
+        assert_eq!(tones_for('E'), None);
+    }
+
+    #[test]
+    fn every_digit_gets_a_tone_and_a_gap() {
+        let source = dtmf_source("123", Duration::from_millis(100), Duration::from_millis(50)).unwrap();
+        assert_eq!(source.total_duration(), Some(Duration::from_millis(450)));
+    }
+
+    #[test]
+    fn the_tones_together_dont_clip() {
+        let source = dtmf_source("*", Duration::from_millis(100), Duration::ZERO).unwrap();
+        assert!(source.into_iter().all(|sample| sample.abs() <= 0.5));
+    }
+
+    #[test]
+    fn anything_off_the_keypad_is_rejected() {
+        assert!(dtmf_source("12E", Duration::from_millis(100), Duration::ZERO).is_err());
+        assert!(dtmf_source("", Duration::from_millis(100), Duration::ZERO).is_err());
+    }
+}
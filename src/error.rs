@@ -0,0 +1,143 @@
+use std::io;
+use std::time::Duration;
+use thiserror::Error;
+
+// Everything that the Player can fail with, so that a daemon built on top of it can tell (say) a
+// missing sound card from a busy channel and retry, skip or alert accordingly.
+//
+// The PTT backends, the config file and the Morse and DTMF encoders report their failures as
+// free-form anyhow errors, which are passed through unchanged in the transparent variants. The
+// exception is the TTY that keys the radio on Unix, whose errno comes out in TtyOpen and Ioctl.
+#[derive(Debug, Error)]
+pub enum PlayerError {
+    #[error("No audio output device was given")]
+    NoAudioDevice,
+
+    #[error("No TTY device was given for PTT")]
+    NoTty,
+
+    // List what's there so that the user doesn't need to go and find out
+    #[error("Failed to find audio device '{name}'. Available devices are:\n  {}", .available.join("\n  "))]
+    DeviceNotFound { name: String, available: Vec<String> },
+
+    #[error("Audio device '{name}' is ambiguous, it matches:\n  {}", .matches.join("\n  "))]
+    AmbiguousDevice { name: String, matches: Vec<String> },
+
+    #[error("Invalid audio device pattern '{pattern}'")]
+    DevicePattern { pattern: String, #[source] source: regex::Error },
+
+    #[error("Failed to enumerate output devices")]
+    DeviceList(#[from] rodio::DevicesError),
+
+    #[error("Failed to open output stream for device")]
+    Stream(#[from] rodio::StreamError),
+
+    #[error("Failed to create Sink from output device")]
+    Sink(#[from] rodio::PlayError),
+
+    #[error("Failed to open audio file '{path}'")]
+    FileOpen { path: String, #[source] source: io::Error },
+
+    #[error("Failed to create decoder for audio file '{path}'")]
+    Decode { path: String, #[source] source: rodio::decoder::DecoderError },
+
+    #[error("Failed to read audio from stdin")]
+    Stdin(#[source] io::Error),
+
+    #[error("Failed to create decoder for audio stream")]
+    DecodeStream(#[source] rodio::decoder::DecoderError),
+
+    #[error("Cannot play {playing}, which has {channels} channels at {sample_rate}Hz")]
+    UnsupportedFormat { playing: String, channels: u16, sample_rate: u32 },
+
+    // The format of what's playing can't be seeked in. 'source_type' is the Rust type that gave up.
+    #[error("Cannot seek in {playing}, as it's {source_type} which doesn't support seeking")]
+    SeekUnsupported { playing: String, source_type: &'static str },
+
+    #[error("Failed to seek in {playing}: {message}")]
+    Seek { playing: String, message: String },
+
+    #[error("Cannot generate a tone at {0}Hz")]
+    InvalidFrequency(f32),
+
+    // For more than one tone at a time, this is their total, since that's what could clip
+    #[error("Cannot generate tones at an amplitude of {0}, which must be between 0 and 1")]
+    InvalidAmplitude(f32),
+
+    #[error("Cannot send audio 0 times, it has to go out at least once")]
+    NoRepeats,
+
+    #[error("Cannot tune for {requested:?}, which is longer than the limit of {limit:?}")]
+    TuneTooLong { requested: Duration, limit: Duration },
+
+    // Opening the TTY for PTT, e.g. it doesn't exist (ENOENT) or we don't have permission to use
+    // it (EACCES)
+    #[cfg(unix)]
+    #[error("Failed to open TTY device '{path}'")]
+    TtyOpen { path: String, #[source] source: nix::Error },
+
+    // Opening any other PTT backend (a GPIO pin, the serialport crate, ...)
+    #[error(transparent)]
+    PttOpen(anyhow::Error),
+
+    // Keying, un-keying or querying the radio with an ioctl on its TTY failed. ENODEV or EIO means
+    // that the adapter went away and couldn't be reopened.
+    #[cfg(unix)]
+    #[error("{what}")]
+    Ioctl { what: String, #[source] source: nix::Error },
+
+    // Keying, un-keying or querying the radio through any other PTT backend (a rigctld command,
+    // ...) failed
+    #[error(transparent)]
+    Ptt(anyhow::Error),
+
+    #[error("Cannot play because streaming is already in progress")]
+    AlreadyTransmitting,
+
+    #[error("Cannot play because streaming is already paused")]
+    AlreadyPaused,
+
+    #[error("Cannot key the radio with no audio while there's audio queued")]
+    AudioQueued,
+
+    #[error("Cannot play because the channel is busy")]
+    ChannelBusy,
+
+    #[error("Gave up waiting for the channel to clear after {timeout:?}")]
+    ChannelBusyTimeout { timeout: Duration },
+
+    #[error("Cannot play because the previous transmission exceeded the transmit time limit")]
+    TimedOut,
+
+    #[error("Cannot play because the transmitter would be keyed for {:.0}% of the last {window:?}, over the limit of {:.0}%",
+            .duty * 100.0, .limit * 100.0)]
+    DutyCycleExceeded { duty: f32, limit: f32, window: Duration },
+
+    // The thread behind a PlayerHandle has gone away, so there's nobody to carry out commands
+    #[error("The player thread has exited")]
+    PlayerExited,
+
+    #[error("Failed to install signal handler")]
+    SignalHandler(#[from] ctrlc::Error),
+
+    #[error(transparent)]
+    Config(anyhow::Error),
+
+    #[error(transparent)]
+    TransmissionLog(anyhow::Error),
+
+    #[error(transparent)]
+    Autodetect(anyhow::Error),
+
+    #[error(transparent)]
+    Cw(anyhow::Error),
+
+    #[error(transparent)]
+    Dtmf(anyhow::Error),
+
+    #[cfg(feature = "tts")]
+    #[error("Failed to synthesize speech")]
+    Tts(#[source] anyhow::Error),
+}
+
+pub type Result<T, E = PlayerError> = std::result::Result<T, E>;
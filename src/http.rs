@@ -0,0 +1,92 @@
+use std::error::Error;
+use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+use crate::error::PlayerError;
+use crate::player::Player;
+
+// A small JSON API for driving a Player over the network, e.g. from a home automation system:
+//
+//   POST /play, /pause and /stop      call the Player method of the same name
+//   POST /queue {"path": "..."}       queues a file, see Player::queue_audio(); "label" names it
+//   GET  /state                       {"state": "idle", "queued": 0, "playing": null}
+//
+// Everything else that succeeds returns {"ok": true}, and failures {"error": "..."}. There's no
+// authentication whatsoever, so this should only ever listen somewhere trusted.
+
+#[derive(Deserialize)]
+struct QueueRequest {
+    path: String,
+    label: Option<String>,
+}
+
+// Serves requests on 'address' (e.g. "0.0.0.0:8080") until the server fails. Requests are handled
+// one at a time, in the order they arrive, so they can't interleave on the Player.
+pub fn serve(player: Arc<Player>, address: &str) -> Result<()> {
+    let server = Server::http(address).map_err(|e| anyhow!("Failed to listen on {}: {}", address, e))?;
+    info!("Listening for HTTP requests on {}", address);
+
+    for request in server.incoming_requests() {
+        handle(&player, request);
+    }
+    Ok(())
+}
+
+fn handle(player: &Player, mut request: Request) {
+    let (status, body) = match (request.method(), request.url()) {
+        (Method::Get, "/state") => match player.state() {
+            Ok(state) => (200, json!({"state": state, "queued": player.queue_len(),
+                                      "playing": player.current_item().map(|item| item.label.unwrap_or(item.description))})),
+            Err(e) => error_response(&e),
+        },
+        (Method::Post, "/play") => done(player.play()),
+        (Method::Post, "/pause") => done(player.pause()),
+        (Method::Post, "/stop") => done(player.stop()),
+        (Method::Post, "/queue") => {
+            let mut body = String::new();
+            match request.as_reader().read_to_string(&mut body).map_err(anyhow::Error::from)
+                .and_then(|_| Ok(serde_json::from_str::<QueueRequest>(&body)?)) {
+                Ok(QueueRequest{path, label: Some(label)}) => done(player.queue_audio_labelled(path, label)),
+                Ok(QueueRequest{path, label: None}) => done(player.queue_audio(path)),
+                Err(e) => (400, json!({"error": format!("Expected {{\"path\": \"...\"}}: {}", e)})),
+            }
+        }
+        (_, "/state" | "/play" | "/pause" | "/stop" | "/queue") => (405, json!({"error": "Method not allowed"})),
+        _ => (404, json!({"error": "Not found"})),
+    };
+
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", "application/json").expect("Invalid header"));
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to send HTTP response: {}", e);
+    }
+}
+
+fn done(result: crate::error::Result<()>) -> (u16, Value) {
+    match result {
+        Ok(()) => (200, json!({"ok": true})),
+        Err(e) => error_response(&e),
+    }
+}
+
+fn error_response(error: &PlayerError) -> (u16, Value) {
+    // Telling the client off for asking at the wrong time is different to breaking
+    let status = match error {
+        PlayerError::AlreadyTransmitting | PlayerError::AlreadyPaused | PlayerError::ChannelBusy
+            | PlayerError::AudioQueued | PlayerError::TimedOut | PlayerError::DutyCycleExceeded{..} => 409,
+        PlayerError::FileOpen{..} | PlayerError::Decode{..} => 400,
+        _ => 500,
+    };
+    // Include the causes too, like anyhow's "{:#}" does
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message += &format!(": {}", cause);
+        source = cause.source();
+    }
+    (status, json!({"error": message}))
+}
@@ -0,0 +1,18 @@
+#[cfg(feature = "async")]
+pub mod async_player;
+pub mod audio;
+pub mod autodetect;
+pub mod config;
+pub mod dtmf;
+pub mod error;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod morse;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod player;
+pub mod ptt;
+pub mod scheduler;
+pub mod transmission_log;
+#[cfg(feature = "tts")]
+pub mod tts;
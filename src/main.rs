@@ -1,39 +1,250 @@
-use std::{thread, time::Duration};
-use std::io::stdin;
-use anyhow::{Context, Result};
-mod player;
+use std::path::PathBuf;
+use std::time::Duration;
+#[cfg(any(feature = "http", feature = "mqtt"))]
+use std::{sync::Arc, thread};
+use anyhow::{anyhow, Context, Result};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap::error::ErrorKind;
+use env_logger::Env;
+use rplayer::{autodetect, player, ptt};
+use rplayer::config::Config;
+use rplayer::player::{Player, PttLine};
 
+#[derive(Parser)]
+#[command(version, about = "Transmits audio files over a radio, keying it through a serial port")]
+#[command(args_conflicts_with_subcommands = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Station profile (TOML) to read settings from; command line options override it
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Serial device whose control line keys the radio [default: the Digirig's]
+    #[arg(long)]
+    tty: Option<String>,
+
+    /// Audio output device connected to the radio (see list-devices) [default: the Digirig's]
+    #[arg(long)]
+    audio_device: Option<String>,
+
+    /// Serial number of the Digirig to use, if more than one is plugged in
+    #[arg(long)]
+    digirig_serial: Option<String>,
+
+    /// Modem control line that keys the radio: rts or dtr [default: rts]
+    #[arg(long, value_parser = parse_ptt_line)]
+    ptt_line: Option<PttLine>,
+
+    /// Milliseconds between keying the radio and starting the audio [default: 250]
+    #[arg(long, value_name = "MS")]
+    lead_ms: Option<u64>,
+
+    /// Milliseconds between the end of the audio and un-keying the radio [default: 250]
+    #[arg(long, value_name = "MS")]
+    tail_ms: Option<u64>,
+
+    /// Callsign to send in Morse code as the automatic ID (see the config file for how often)
+    #[arg(long)]
+    callsign: Option<String>,
+
+    /// Play the audio without keying the radio, e.g. to set levels on a local sound card
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Serve the HTTP control API on this address (e.g. 0.0.0.0:8080) instead of transmitting
+    /// straight away
+    #[cfg(feature = "http")]
+    #[arg(long, value_name = "ADDRESS")]
+    http: Option<String>,
+
+    // The subcommands as they were before there were subcommands
+    #[arg(long, hide = true)]
+    list_devices: bool,
+    #[arg(long, hide = true)]
+    list_ports: bool,
+
+    /// An audio file to transmit, the same as giving it after the options
+    #[arg(long = "file", value_name = "FILE")]
+    file_options: Vec<String>,
+
+    /// Audio files to transmit, back-to-back as a single transmission, after which rplayer exits.
+    /// With a remote control (--http or MQTT) these are just queued, ready for the first play
+    /// command. "-" on its own reads the audio from stdin.
+    files: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the available audio output devices
+    ListDevices,
+    /// Print the serial ports that could be used for PTT
+    ListPorts,
+    /// Key the radio with no audio for a few seconds (at most 30), e.g. to check the SWR
+    TestKey {
+        seconds: u64,
+    },
+}
+
+impl Args {
+    // --file and the rest, in that order
+    fn files(&self) -> Vec<String> {
+        self.file_options.iter().chain(&self.files).cloned().collect()
+    }
+}
+
+fn parse_ptt_line(line: &str) -> Result<PttLine> {
+    match line.to_ascii_lowercase().as_str() {
+        "rts" => Ok(PttLine::Rts),
+        "dtr" => Ok(PttLine::Dtr),
+        _ => Err(anyhow!("expected rts or dtr")),
+    }
+}
 
 fn main() -> Result<()> {
-    let audio_file = "./test_transmission.mp3";
-    
-    loop {
-        let player = player::Player::for_devices("/dev/ttyUSB0".to_string(), "front:CARD=Device,DEV=0".to_string())
-            .context("Failed to initialize player")?;
-
-        player.queue_audio(audio_file.to_string())?;
-
-        let mut is_paused = true;
-        let mut count = 0;
-        loop {
-            let mut s = String::new();
-            println!("Press ENTER to play/pause audio");
-            stdin().read_line(&mut s).context("Failed to read user input")?;
-
-            if is_paused {
-                player.play()?;
-            } else {
-                player.pause()?;
-            }
-            is_paused = !is_paused;
-            count += 1;
-            if count > 1 {
-                break
+    let args = Args::parse();
+    // Info is what says what's being transmitted, so that's shown unless RUST_LOG says otherwise
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    let test_key = match args.command {
+        Some(Command::ListDevices) => return list_devices(),
+        Some(Command::ListPorts) => return list_ports(),
+        Some(Command::TestKey{seconds}) => Some(Duration::from_secs(seconds)),
+        None if args.list_devices => return list_devices(),
+        None if args.list_ports => return list_ports(),
+        None => None,
+    };
+
+    let mut config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let files = args.files();
+    if files.is_empty() && test_key.is_none() && !remote_controlled(&args, &config) {
+        Args::command().error(ErrorKind::MissingRequiredArgument, "No audio files given to transmit").exit();
+    }
+    if files.len() > 1 && files.iter().any(|file| file == "-") {
+        Args::command().error(ErrorKind::ArgumentConflict, "Audio from stdin (-) can't be sent along with files").exit();
+    }
+    if args.tty.is_some() {
+        config.tty.clone_from(&args.tty);
+    }
+    if args.audio_device.is_some() {
+        config.audio_device.clone_from(&args.audio_device);
+    }
+    config.ptt_line = args.ptt_line.or(config.ptt_line);
+    config.lead_ms = args.lead_ms.or(config.lead_ms);
+    config.tail_ms = args.tail_ms.or(config.tail_ms);
+    if args.callsign.is_some() {
+        config.callsign.clone_from(&args.callsign);
+    }
+    if args.dry_run {
+        config.dry_run = Some(true);
+    }
+    config.validate().context("Invalid options")?;
+
+    // Only go looking for a Digirig if we haven't been told exactly what to use (a dry run doesn't
+    // need the tty)
+    let dry_run = config.dry_run == Some(true);
+    if (config.tty.is_none() && !dry_run) || config.audio_device.is_none() {
+        let digirig = autodetect::find_digirig(args.digirig_serial.as_deref())
+            .context("Failed to find a Digirig, use --tty and --audio-device instead")?;
+        config.tty.get_or_insert(digirig.tty_path);
+        config.audio_device.get_or_insert(digirig.audio_device);
+    }
+
+    if let Some(duration) = test_key {
+        return Ok(build_player(&config)?.test_key(duration)?);
+    }
+    #[cfg(any(feature = "http", feature = "mqtt"))]
+    if remote_controlled(&args, &config) {
+        return serve(&args, &config, &files);
+    }
+
+    // One transmission of everything, released early (and cleanly) by Ctrl-C
+    let player = build_player(&config)?;
+    queue(&player, &files)?;
+    player.play()?;
+    player.wait_until_empty()?;
+    Ok(())
+}
+
+fn list_devices() -> Result<()> {
+    for name in player::Player::list_output_devices()? {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
+fn list_ports() -> Result<()> {
+    for port in ptt::list_serial_ports()? {
+        let mut line = port.path;
+        if let Some(usb) = port.usb {
+            line += &format!(" [{:04x}:{:04x}] {} {}", usb.vid, usb.pid,
+                             usb.manufacturer.unwrap_or_default(), usb.product.unwrap_or_default());
+            if let Some(serial) = usb.serial_number {
+                line += &format!(" (serial {})", serial);
             }
         }
+        if port.in_use == Some(true) {
+            line += " (in use)";
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+// Either the files or, for "-", whatever's piped in
+fn queue(player: &Player, files: &[String]) -> Result<()> {
+    if files == ["-"] {
+        player.queue_stdin()?;
+    } else if !files.is_empty() {
+        player.queue_many(files)?;
+    }
+    Ok(())
+}
+
+fn build_player(config: &Config) -> Result<Player> {
+    config.builder()?
+        .signal_handler(true)
+        .build()
+        .context("Failed to initialize player")
+}
+
+// Whether something other than the command line is going to be driving the player. Which of the
+// arguments this looks at depends on the features turned on.
+#[allow(unused_variables)]
+fn remote_controlled(args: &Args, config: &Config) -> bool {
+    #[cfg(feature = "http")]
+    if args.http.is_some() {
+        return true;
+    }
+    #[cfg(feature = "mqtt")]
+    if config.mqtt.is_some() {
+        return true;
+    }
+    false
+}
+
+// Hands the player over to the HTTP API and/or MQTT, whichever are turned on
+#[cfg(any(feature = "http", feature = "mqtt"))]
+#[allow(unused_variables)]
+fn serve(args: &Args, config: &Config, files: &[String]) -> Result<()> {
+    let player = Arc::new(build_player(config)?);
+    queue(&player, files)?;
 
-        println!("Starting all over again in 2 seconds...");
-        thread::sleep(Duration::from_secs(2));
-        println!("Let's go!");
+    #[cfg(feature = "mqtt")]
+    if let Some(mqtt) = config.mqtt.clone() {
+        let player = Arc::clone(&player);
+        thread::spawn(move || rplayer::mqtt::run(player, &mqtt));
+    }
+    #[cfg(feature = "http")]
+    if let Some(address) = &args.http {
+        return rplayer::http::serve(player, address);
+    }
+    // Only MQTT is left, which carries on in the background forever
+    loop {
+        thread::park();
     }
 }
@@ -0,0 +1,190 @@
+use std::f32::consts::PI;
+use anyhow::{anyhow, Result};
+use rodio::buffer::SamplesBuffer;
+
+const SAMPLE_RATE:u32 = 48000;
+
+// Leaves headroom so that the tone doesn't clip once the sink's volume is applied
+const AMPLITUDE:f32 = 0.5;
+
+// Each dit and dah is shaped with a raised-cosine ramp this long, so that keying the tone on and
+// off doesn't splatter clicks across the band
+const RAMP_SECS:f32 = 0.005;
+
+fn code_for(c: char) -> Option<&'static str> {
+    let code = match c.to_ascii_uppercase() {
+        'A' => ".-",    'B' => "-...",  'C' => "-.-.",  'D' => "-..",   'E' => ".",
+        'F' => "..-.",  'G' => "--.",   'H' => "....",  'I' => "..",    'J' => ".---",
+        'K' => "-.-",   'L' => ".-..",  'M' => "--",    'N' => "-.",    'O' => "---",
+        'P' => ".--.",  'Q' => "--.-",  'R' => ".-.",   'S' => "...",   'T' => "-",
+        'U' => "..-",   'V' => "...-",  'W' => ".--",   'X' => "-..-",  'Y' => "-.--",
+        'Z' => "--..",
+        '0' => "-----", '1' => ".----", '2' => "..---", '3' => "...--", '4' => "....-",
+        '5' => ".....", '6' => "-....", '7' => "--...", '8' => "---..", '9' => "----.",
+        '.' => ".-.-.-", ',' => "--..--", '?' => "..--..", '/' => "-..-.", '=' => "-...-",
+        '+' => ".-.-.",  '-' => "-....-", '@' => ".--.-.", '\'' => ".----.", ':' => "---...",
+        _ => return None,
+    };
+    Some(code)
+}
+
+// A keyed (tone) or un-keyed (silence) period, measured in dits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Element {
+    keyed: bool,
+    dits: u32,
+}
+
+// Separates what's been sent so far from whatever comes next. There's never any silence at the
+// start, and a longer gap (e.g. between words) swallows a shorter one (e.g. between characters).
+fn gap(elements: &mut Vec<Element>, dits: u32) {
+    match elements.last_mut() {
+        None => {},
+        Some(last) if !last.keyed => last.dits = last.dits.max(dits),
+        Some(_) => elements.push(Element{keyed: false, dits}),
+    }
+}
+
+// Standard (non-Farnsworth) timing: a dah is three dits, elements within a character are one dit
+// apart, characters are three dits apart and words are seven dits apart. Prosigns are written in
+// angle brackets (e.g. "<SK>") and are sent as a single character with no gaps between letters.
+pub(crate) fn encode(text: &str) -> Result<Vec<Element>> {
+    let mut elements:Vec<Element> = Vec::new();
+
+    let mut in_prosign = false;
+    for c in text.chars() {
+        match c {
+            '<' if !in_prosign => {
+                gap(&mut elements, 3);
+                in_prosign = true;
+                continue;
+            },
+            '>' if in_prosign => {
+                in_prosign = false;
+                continue;
+            },
+            c if c.is_whitespace() && !in_prosign => {
+                gap(&mut elements, 7);
+                continue;
+            },
+            _ => {},
+        }
+
+        let code = code_for(c).ok_or_else(|| anyhow!("Can't send '{}' in Morse code", c))?;
+        // Letters of a prosign run together as if they were a single character
+        let char_gap = if in_prosign && elements.last().is_some_and(|e| e.keyed) { 1 } else { 3 };
+        gap(&mut elements, char_gap);
+        for (i, symbol) in code.chars().enumerate() {
+            if i > 0 {
+                elements.push(Element{keyed: false, dits: 1});
+            }
+            elements.push(Element{keyed: true, dits: if symbol == '-' { 3 } else { 1 }});
+        }
+    }
+    if in_prosign {
+        return Err(anyhow!("Unterminated prosign in '{}'", text));
+    }
+
+    // Trailing silence would only hold the transmitter open for nothing
+    while elements.last().is_some_and(|e| !e.keyed) {
+        elements.pop();
+    }
+
+    Ok(elements)
+}
+
+// Synthesizes 'text' as Morse code at 'wpm' words per minute (using PARIS timing, so a dit lasts
+// 1.2 / wpm seconds) with a sidetone of 'tone_hz'
+pub fn cw_source(text: &str, wpm: u32, tone_hz: f32) -> Result<SamplesBuffer<f32>> {
+    if wpm == 0 {
+        return Err(anyhow!("Morse code speed must be at least 1 WPM"));
+    }
+    if !(tone_hz > 0.0 && tone_hz < SAMPLE_RATE as f32 / 2.0) {
+        return Err(anyhow!("Morse code tone of {} Hz can't be generated", tone_hz));
+    }
+
+    let dit_samples = (SAMPLE_RATE as f32 * 1.2 / wpm as f32) as usize;
+    let ramp_samples = ((SAMPLE_RATE as f32 * RAMP_SECS) as usize).min(dit_samples / 2);
+
+    let mut samples:Vec<f32> = Vec::new();
+    for element in encode(text)? {
+        let len = dit_samples * element.dits as usize;
+        if !element.keyed {
+            samples.resize(samples.len() + len, 0.0);
+            continue;
+        }
+
+        for i in 0..len {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let edge = i.min(len - 1 - i);
+            let envelope = if edge < ramp_samples {
+                0.5 - 0.5 * (PI * edge as f32 / ramp_samples as f32).cos()
+            } else {
+                1.0
+            };
+            samples.push(AMPLITUDE * envelope * (2.0 * PI * tone_hz * t).sin());
+        }
+    }
+
+    Ok(SamplesBuffer::new(1, SAMPLE_RATE, samples))
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::Source;
+    use super::*;
+
+    fn keyed(dits: u32) -> Element {
+        Element{keyed: true, dits}
+    }
+
+    fn silent(dits: u32) -> Element {
+        Element{keyed: false, dits}
+    }
+
+    #[test]
+    fn letters_are_dits_and_dahs() {
+        assert_eq!(encode("A").unwrap(), vec![keyed(1), silent(1), keyed(3)]);
+    }
+
+    #[test]
+    fn characters_and_words_are_spaced() {
+        assert_eq!(encode("EE").unwrap(), vec![keyed(1), silent(3), keyed(1)]);
+        assert_eq!(encode("E  E").unwrap(), vec![keyed(1), silent(7), keyed(1)]);
+    }
+
+    #[test]
+    fn there_is_no_silence_at_either_end() {
+        assert_eq!(encode(" E ").unwrap(), vec![keyed(1)]);
+    }
+
+    #[test]
+    fn prosigns_run_together() {
+        // <AR> is .-.-. rather than .- .-.
+        assert_eq!(encode("<AR>").unwrap(), encode("+").unwrap());
+    }
+
+    #[test]
+    fn callsigns_with_a_slash() {
+        assert!(encode("N0CALL/P").is_ok());
+    }
+
+    #[test]
+    fn unsendable_text_is_rejected() {
+        assert!(encode("N0CALL~").is_err());
+        assert!(encode("<AR").is_err());
+    }
+
+    #[test]
+    fn dits_last_as_long_as_the_speed_says() {
+        // At 12 WPM a dit is 100ms
+        let source = cw_source("E", 12, 700.0).unwrap();
+        assert_eq!(source.total_duration(), Some(std::time::Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn speed_and_tone_are_checked() {
+        assert!(cw_source("E", 0, 700.0).is_err());
+        assert!(cw_source("E", 20, 30000.0).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use log::{error, info, warn};
+use rumqttc::{Client, Connection, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::Deserialize;
+use crate::player::{Player, PlayerState};
+
+// How often the Player is checked for a change of state to publish, since transmissions end (and
+// time out) by themselves
+const STATE_POLL_INTERVAL:Duration = Duration::from_millis(250);
+
+// How long to wait before trying the broker again when the connection drops
+const RECONNECT_DELAY:Duration = Duration::from_secs(5);
+
+// Drives a Player from an MQTT broker. Commands are sent to 'command_topic' as plain text:
+//
+//   play, pause, stop       call the Player method of the same name
+//   queue:<path>            queues a file, see Player::queue_audio()
+//
+// The Player's state ("idle", "transmitting", ...) is published to 'state_topic' whenever it
+// changes, retained so that late subscribers see it too, and anything that fails is published
+// to '<state_topic>/error'. If we drop off the broker, it sets the state to "offline" for us.
+//
+// In a config file this is the [mqtt] table, e.g.
+//
+//   [mqtt]
+//   host = "broker.local"
+//   command_topic = "shack/rplayer/cmd"
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub command_topic: String,
+    pub state_topic: String,
+}
+
+impl Default for MqttConfig {
+    fn default() -> MqttConfig {
+        MqttConfig{
+            host: "localhost".to_string(),
+            port: 1883,
+            client_id: "rplayer".to_string(),
+            command_topic: "rplayer/cmd".to_string(),
+            state_topic: "rplayer/state".to_string(),
+        }
+    }
+}
+
+// Connects to the broker and carries out commands for as long as the MQTT client lasts, which is
+// forever; a broker that goes away is retried until it comes back.
+pub fn run(player: Arc<Player>, config: &MqttConfig) {
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options
+        .set_keep_alive(Duration::from_secs(30))
+        .set_last_will(LastWill::new(&config.state_topic, "offline", QoS::AtLeastOnce, true));
+    let (client, connection) = Client::new(options, 10);
+    info!("Connecting to MQTT broker {}:{}", config.host, config.port);
+
+    // Every connection is a clean session, so that commands sent while we were away aren't
+    // suddenly carried out when we come back
+    let connected = Arc::new(AtomicBool::new(false));
+    let announcer = StateAnnouncer{
+        player: Arc::clone(&player),
+        client: client.clone(),
+        command_topic: config.command_topic.clone(),
+        state_topic: config.state_topic.clone(),
+        connected: Arc::clone(&connected),
+    };
+    thread::spawn(move || announcer.run());
+
+    handle_commands(&player, &client, connection, config, &connected);
+}
+
+// This is the thread that drives the connection, so it mustn't ever block on the client's
+// request queue (which is only emptied by the connection); anything that might is left to the
+// StateAnnouncer.
+fn handle_commands(player: &Player, client: &Client, mut connection: Connection, config: &MqttConfig, connected: &AtomicBool) {
+    let error_topic = format!("{}/error", config.state_topic);
+
+    for notification in connection.iter() {
+        match notification {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("Connected to MQTT broker");
+                connected.store(true, Ordering::SeqCst);
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) if publish.topic == config.command_topic => {
+                let command = String::from_utf8_lossy(&publish.payload);
+                if let Err(e) = carry_out(player, command.trim()) {
+                    error!("MQTT command '{}' failed: {:#}", command.trim(), e);
+                    // It's been logged, so there's no harm in it not making it out too
+                    let _ = client.try_publish(&error_topic, QoS::AtLeastOnce, false, format!("{:#}", e));
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!("MQTT connection failed, retrying in {:?}: {}", RECONNECT_DELAY, e);
+                thread::sleep(RECONNECT_DELAY);
+            }
+        }
+    }
+}
+
+fn carry_out(player: &Player, command: &str) -> Result<()> {
+    match command {
+        "play" => player.play()?,
+        "pause" => player.pause()?,
+        "stop" => player.stop()?,
+        _ => match command.strip_prefix("queue:") {
+            Some(path) => player.queue_audio(path.to_string())?,
+            None => return Err(anyhow!("Unknown command '{}'", command)),
+        },
+    }
+    Ok(())
+}
+
+// Publishes the Player's state whenever it changes, and subscribes to the commands each time we
+// connect, since the broker forgets about us (and sets the state to "offline") in between
+struct StateAnnouncer {
+    player: Arc<Player>,
+    client: Client,
+    command_topic: String,
+    state_topic: String,
+    connected: Arc<AtomicBool>,
+}
+
+impl StateAnnouncer {
+    fn run(self) {
+        let mut published:Option<PlayerState> = None;
+        loop {
+            if self.connected.swap(false, Ordering::SeqCst) {
+                // These only fail once the client has shut down, at which point there's nobody
+                // left to tell
+                if self.client.subscribe(&self.command_topic, QoS::AtLeastOnce).is_err() {
+                    return;
+                }
+                published = None;
+            }
+
+            match self.player.state() {
+                Ok(state) if published != Some(state) => {
+                    if self.client.publish(&self.state_topic, QoS::AtLeastOnce, true, state.to_string()).is_err() {
+                        return;
+                    }
+                    published = Some(state);
+                }
+                Ok(_) => {}
+                Err(e) => error!("Failed to get the player state: {:#}", e),
+            }
+            thread::sleep(STATE_POLL_INTERVAL);
+        }
+    }
+}
@@ -1,144 +1,3729 @@
-use std::{thread, time::Duration, fs::File};
-use std::io::BufReader;
-use rodio::{Decoder, DeviceTrait, OutputStream, Sink};
+use std::{thread, time::{Duration, Instant}, fs::File};
+use std::collections::VecDeque;
+use std::fmt;
+use std::iter;
+use std::io::{BufReader, Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::os::fd::RawFd;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use rodio::{Decoder, DeviceTrait, OutputStream, Sink, Source};
+use rodio::decoder::DecoderError;
+use rodio::source::{EmptyCallback, SeekError, SineWave, Zero};
 use rodio::cpal;
 use rodio::cpal::traits::HostTrait;
-use anyhow::{anyhow, Context, Result};
-use nix::{fcntl, ioctl_read_bad};
+use log::{debug, error, info, warn};
+use regex::Regex;
+use serde::Serialize;
+use crate::audio::{self, AudioSink, AudioSource};
+use crate::config::Config;
+use crate::error::{PlayerError, Result};
+use crate::{dtmf, morse};
+use crate::ptt::{self, PttControl};
+use crate::transmission_log::TransmissionLog;
 
-const IOCTL_TIOCMGET:i32 = 0x5415;
-const IOCTL_TIOCMSET:i32 = 0x5418;
+pub use crate::audio::{AudioFormat, Normalization, Passband, PassbandPreset};
+pub use crate::ptt::{ModemLine, ModemStatus, PttLine};
 
-const TIOCM_RTS_FLAG:i32 = 0x004;
+// Default time to wait between keying the radio and starting audio (lead), and between stopping
+// audio and un-keying the radio (tail)
+const DEFAULT_PTT_DELAY:Duration = Duration::from_millis(250);
 
-pub struct Player {
-    tty_fd: i32,
+// Unless they're set, files are faded in and out over this fraction of the lead and tail delays
+// (up to MAX_DEFAULT_FADE), so that a radio set up to start talking straight away isn't made to
+// wait for the audio to fade up, and one with time to spare gets a gentler start
+const DEFAULT_FADE_DIVISOR:u32 = 10;
+const MAX_DEFAULT_FADE:Duration = Duration::from_millis(50);
 
-    sink: Sink,
-    // 'stream' must have the same lifetime as 'sink', or audio playback will be halted when 'stream' is dropped
-    #[allow(dead_code)]
-    stream: OutputStream,
+// How often a transmission in progress is checked on
+const TRANSMISSION_POLL_INTERVAL:Duration = Duration::from_millis(50);
+
+// How many times releasing PTT at the end of a test key is tried before giving up, since failing
+// leaves a dead carrier on the air
+const TEST_KEY_RELEASE_ATTEMPTS:u32 = 5;
+
+// How long play_when_clear() wants the channel to be quiet for by default, and how often it looks
+const DEFAULT_CLEAR_HOLD:Duration = Duration::from_secs(2);
+const CHANNEL_POLL_INTERVAL:Duration = Duration::from_millis(100);
+
+// Generated tones are kept below full scale so they don't clip once the volume is applied
+const TONE_AMPLITUDE:f32 = 0.5;
+
+// The most that the volume can ever be turned up to, which is already a lot of gain
+const MAX_VOLUME:f32 = 4.0;
+
+// The pitch of transmit_cw()'s Morse code
+const CW_TONE_HZ:f32 = 700.0;
+
+// The longest that tune() will key the radio for, which is long enough to tune most things
+const MAX_TUNE_DURATION:Duration = Duration::from_secs(30);
+
+// How loud the CTCSS tone is mixed in, relative to full scale. Tones are normally sent at around
+// 15% of the peak deviation, which is what repeaters are set up to decode.
+const CTCSS_LEVEL:f32 = 0.15;
+
+// A short courtesy tone sent at the end of each over, right before PTT drops
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RogerBeep {
+    pub frequency_hz: f32,
+    pub duration: Duration,
 }
 
-impl Player {
-    // Digirig always appears with CARD=Device in the name, and that appears to be unique to
-    // usb-attached sound devices:
-    // # Device: sysdefault:CARD=Device
-    // # Device: front:CARD=Device,DEV=0
-    // # Device: surround40:CARD=Device,DEV=0
-    // # Device: iec958:CARD=Device,DEV=0
-    //
-    //
-    pub fn for_devices(tty_path: String, audio_device_name: String) -> Result<Player> {
-        // Set up audio output
-        let host = cpal::default_host();
-        let output_devs = host
-            .output_devices()
-            .with_context(|| "Failed to enumerate output devices")?;
+impl RogerBeep {
+    fn source(self) -> AudioSource {
+        audio::lasting(Box::new(SineWave::new(self.frequency_hz).amplify(TONE_AMPLITUDE)), self.duration)
+    }
+}
 
-        let mut output_dev:Option<rodio::Device> = None;
-        // List output devices and find our target device
-        for dev in output_devs {
-            if let Ok(name) = dev.name() {
-                if name == audio_device_name {
-                    output_dev = dev.into();
-                }
+impl Default for RogerBeep {
+    fn default() -> RogerBeep {
+        RogerBeep{frequency_hz: 1000.0, duration: Duration::from_millis(150)}
+    }
+}
+
+// Identifying the station in Morse code every so often, without anyone having to remember to,
+// e.g. every 10 minutes for an unattended beacon
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AutoIdConfig {
+    pub callsign: String,
+    pub interval: Duration,
+    pub wpm: u32,
+}
+
+// Protection for a transmitter (or amplifier) that can't be keyed continuously: play() refuses
+// to start a transmission that would leave it keyed for more than 'max' (a fraction, e.g. 0.5 for
+// 50%) of the last 'window'
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DutyCycleLimit {
+    pub window: Duration,
+    pub max: f32,
+}
+
+// Keyed time is kept for this long, which is as long a window as duty_cycle() can look back over
+const MAX_DUTY_CYCLE_WINDOW:Duration = Duration::from_secs(24 * 60 * 60);
+
+// When PTT has been keyed and released, for working out the duty cycle. The last period is still
+// open (None) while the radio is keyed.
+#[derive(Default)]
+struct KeyedPeriods {
+    periods: VecDeque<(Instant, Option<Instant>)>,
+}
+
+impl KeyedPeriods {
+    fn keyed(&mut self, at: Instant) {
+        // Keying again while keyed is still the same period
+        if !self.periods.back().is_some_and(|(_, released)| released.is_none()) {
+            self.periods.push_back((at, None));
+        }
+        while self.periods.front().is_some_and(|&(_, released)| released.is_some_and(|released| at - released > MAX_DUTY_CYCLE_WINDOW)) {
+            self.periods.pop_front();
+        }
+    }
+
+    fn released(&mut self, at: Instant) {
+        if let Some((_, released @ None)) = self.periods.back_mut() {
+            *released = Some(at);
+        }
+    }
+
+    // How much of the 'window' up to 'now' the radio was keyed for
+    fn keyed_time(&self, window: Duration, now: Instant) -> Duration {
+        let start = now.checked_sub(window);
+        self.periods.iter()
+            .map(|&(keyed, released)| {
+                let from = start.map_or(keyed, |start| keyed.max(start));
+                released.unwrap_or(now).min(now).saturating_duration_since(from)
+            })
+            .sum()
+    }
+}
+
+// The automatic ID as it's sent. A slot for it is queued ahead of every file, and when the sink
+// gets to one it's filled if the ID is due, so that the ID only ever goes out between files
+// rather than in the middle of one. How long ago the last one went out goes by when the ID was
+// actually sent, not when the slot was queued.
+struct AutoId {
+    callsign: String,
+    // The ID, ready to go
+    samples: Vec<f32>,
+    channels: u16,
+    sample_rate: u32,
+    interval: Duration,
+    last_sent: Mutex<Option<Instant>>,
+}
+
+impl AutoId {
+    fn new(config: AutoIdConfig) -> Result<AutoId> {
+        let id = morse::cw_source(&config.callsign, config.wpm, CW_TONE_HZ).map_err(PlayerError::Cw)?;
+        let (channels, sample_rate) = (id.channels(), id.sample_rate());
+        // A moment's pause, so that the file doesn't start right on the heels of the last dit
+        let samples:Vec<f32> = id.chain(iter::repeat_n(0.0, (sample_rate / 2) as usize * channels as usize)).collect();
+        Ok(AutoId{
+            callsign: config.callsign,
+            channels,
+            sample_rate,
+            samples,
+            interval: config.interval,
+            last_sent: Mutex::new(None),
+        })
+    }
+
+    fn is_due(&self) -> bool {
+        self.last_sent.lock().unwrap_or_else(PoisonError::into_inner).is_none_or(|sent| sent.elapsed() >= self.interval)
+    }
+
+    // How long the ID will take, for a slot queued now. That's a guess, since it's only decided
+    // once the slot comes around, which could be after it's due.
+    fn expected_duration(&self) -> Duration {
+        match self.is_due() {
+            true => Duration::from_secs_f64(self.samples.len() as f64 / (self.channels as f64 * self.sample_rate as f64)),
+            false => Duration::ZERO,
+        }
+    }
+
+    // Whether the ID should go out now, in which case it's taken to have been sent
+    fn take_if_due(&self) -> bool {
+        let mut last_sent = self.last_sent.lock().unwrap_or_else(PoisonError::into_inner);
+        let due = last_sent.is_none_or(|sent| sent.elapsed() >= self.interval);
+        if due {
+            *last_sent = Some(Instant::now());
+        }
+        due
+    }
+}
+
+struct IdSlot {
+    auto_id: Arc<AutoId>,
+    // None until the slot's been reached, and then empty if the ID wasn't due
+    id: Option<std::vec::IntoIter<f32>>,
+}
+
+impl Iterator for IdSlot {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let auto_id = &self.auto_id;
+        self.id.get_or_insert_with(|| match auto_id.take_if_due() {
+            true => {
+                info!("Sending the automatic ID '{}'", auto_id.callsign);
+                auto_id.samples.clone().into_iter()
             }
-        };
+            false => Vec::new().into_iter(),
+        }).next()
+    }
+}
 
-        // We assert that the Option is not None with .context()
-        let output_dev = output_dev.context(format!("Failed to find audio device '{}'", audio_device_name))?;
+impl Source for IdSlot {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.auto_id.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.auto_id.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
 
-        // If 'stream' is dropped, the stream_handle and sink are useless. See this note from the
-        // rodio documentation:
-        //   > If [the OutputStream] is dropped playback will end [and] attached OutputStreamHandles will no longer work.
-        let (stream, stream_handle) = OutputStream::try_from_device(&output_dev).unwrap();
-        let sink = Sink::try_new(&stream_handle).context("Failed to create Sink from output device")?;
+// Something queued on the sink
+enum Queued {
+    File(String),
+    Stream,
+    Cw(String),
+    #[cfg(feature = "tts")]
+    Speech(String),
+    Dtmf(String),
+    Tone(f32),
+    TwoTone(f32, f32),
+    RogerBeep,
+    Gap,
+    Carrier,
+    AutoId,
+}
 
-        // Set up TTY device
-        let tty_fd =  fcntl::open(tty_path.as_str(), fcntl::OFlag::O_RDWR,
-                                    nix::sys::stat::Mode::S_IRWXU)
-            .context("Failed to open TTY device")?;
-        // Ensure that RTS is NOT asserted so we don't hold open the RF link on startup
-        let player = Player{tty_fd, sink, stream};
-        if player.rts_is_enabled()? {
-            player.toggle_rts()?
+impl Queued {
+    fn describe(&self) -> String {
+        match self {
+            Queued::File(path) => format!("'{}'", path),
+            Queued::Stream => "the audio stream".to_string(),
+            Queued::Cw(text) => format!("the Morse code '{}'", text),
+            #[cfg(feature = "tts")]
+            Queued::Speech(text) => format!("the announcement '{}'", text),
+            Queued::Dtmf(digits) => format!("the DTMF digits '{}'", digits),
+            Queued::Tone(frequency_hz) => format!("a {}Hz tone", frequency_hz),
+            Queued::TwoTone(f1_hz, f2_hz) => format!("a {}Hz + {}Hz two-tone signal", f1_hz, f2_hz),
+            Queued::RogerBeep => "the roger beep".to_string(),
+            Queued::Gap => "a gap".to_string(),
+            Queued::Carrier => "a bare carrier".to_string(),
+            Queued::AutoId => "the automatic ID".to_string(),
         }
+    }
 
-        Ok(player)
+    // Whether this is something that was asked for, rather than something that goes along with it
+    // (like the roger beep, or the gaps between files)
+    fn is_item(&self) -> bool {
+        match self {
+            Queued::File(_) | Queued::Stream | Queued::Cw(_) | Queued::Dtmf(_) => true,
+            #[cfg(feature = "tts")]
+            Queued::Speech(_) => true,
+            _ => false,
+        }
     }
+}
 
-    pub fn queue_audio(self: &Player, audiofile_path: String) -> Result<()> {
-        let file = BufReader::new(File::open(&audiofile_path).context("Failed to open audio file")?);
-        let source = Decoder::new(file).context("Failed to create decoder for audio file")?;
+// One of the things in the queue, as far as a UI or a log is concerned (see Player::current_item())
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueItem {
+    // E.g. "'weather.mp3'" or "the Morse code 'CQ'", as it goes in the log
+    pub description: String,
+    // For files
+    pub path: Option<String>,
+    // None if it isn't known until it's been played, as for streams
+    pub duration: Option<Duration>,
+    pub label: Option<String>,
+}
 
-        println!("Playing audio file {}", audiofile_path);
-        self.sink.append(source);
-        self.sink.pause();
+// What's been queued on the sink, in order. rodio only knows how many sources are left, so
+// entries are forgotten as that goes down; see Player::lock_queued().
+struct QueueLog {
+    entries: VecDeque<QueueEntry>,
+    // How long the entries that have been forgotten took to play, since the queue was last empty.
+    // None once one of them couldn't say.
+    finished: Option<Duration>,
+}
 
-        Ok(())
+impl Default for QueueLog {
+    fn default() -> QueueLog {
+        QueueLog{entries: VecDeque::new(), finished: Some(Duration::ZERO)}
     }
+}
 
-    pub fn play(self: &Player) -> Result<()> {
-        if self.rts_is_enabled()? || !self.sink.is_paused() {
-            return Err(anyhow!("Cannot play because streaming is already in progress"));
+impl QueueLog {
+    // How long everything since the queue was last empty takes to play, played or not
+    fn total_duration(&self) -> Option<Duration> {
+        self.entries.iter()
+            .filter(|entry| !entry.dropped.load(Ordering::SeqCst))
+            .try_fold(self.finished?, |total, entry| Some(total + entry.duration?))
+    }
+}
+
+struct QueueEntry {
+    queued: Queued,
+    // Whatever the caller wanted to call it, see Player::queue_audio_labelled()
+    label: Option<String>,
+    // Shared with the source's Droppable
+    dropped: Arc<AtomicBool>,
+    // None for streams, and anything else whose length isn't known until it's been played
+    duration: Option<Duration>,
+}
+
+impl QueueEntry {
+    fn item(&self) -> QueueItem {
+        QueueItem{
+            description: self.queued.describe(),
+            path: match &self.queued {
+                Queued::File(path) => Some(path.clone()),
+                _ => None,
+            },
+            duration: self.duration,
+            label: self.label.clone(),
         }
+    }
+}
 
-        self.toggle_rts()?;
-        // Sleep for a short period so that audio doesn't get cut off
-        thread::sleep(Duration::from_millis(250));
-        self.sink.play();
+// Wraps everything queued on the sink, so that it can be dropped (e.g. by clear_queue()) while
+// it's waiting its turn. Once a source has started it plays to the end.
+struct Droppable {
+    source: AudioSource,
+    dropped: Arc<AtomicBool>,
+    started: bool,
+    // What it is and where to say so once it's on the air, for the transmission log
+    aired: Option<(String, Arc<Mutex<Vec<String>>>)>,
+}
 
-        Ok(())
+impl Iterator for Droppable {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if !self.started {
+            self.started = true;
+            if self.dropped.load(Ordering::SeqCst) {
+                return None;
+            }
+        }
+        let sample = self.source.next()?;
+        // Not before now, since some sources (like the ID slot) turn out to be empty
+        if let Some((what, aired)) = self.aired.take() {
+            aired.lock().unwrap_or_else(PoisonError::into_inner).push(what);
+        }
+        Some(sample)
     }
 
-    pub fn pause(self: &Player) -> Result<()> {
-        if !self.rts_is_enabled()? || self.sink.is_paused() {
-            return Err(anyhow!("Cannot play because streaming is already paused"));
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.source.size_hint()
+    }
+}
+
+impl Source for Droppable {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)
+    }
+}
+
+// Whether queue_audio_repeated() keeps the radio keyed through the gaps between repeats
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Repeats {
+    // Everything goes out in a single over
+    KeyedThrough,
+    // Each repeat is an over of its own. Gaps too short to fit both the tail and lead delays are
+    // still sent keyed.
+    UnkeyBetween,
+}
+
+// What the Player is doing, as reported by Player::state(). A transmission goes
+//
+//   Idle --queue_*()--> Paused --play()--> Keyed --lead delay--> Transmitting
+//
+// and then, once the audio runs out (or the time limit is hit, or pause() is called)
+//
+//   Transmitting --> Unkeying --tail delay--> Paused (if there's audio left) or Idle
+//
+// With a hang time (see Player::set_hang_time()), running out of audio goes through Keyed instead,
+// back to Transmitting if play() is called in time and on to Idle if not:
+//
+//   Transmitting --> Keyed --tail delay and hang time--> Idle
+//
+// stop() goes straight back to Idle from anywhere, after the tail delay if the radio was keyed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayerState {
+    // Nothing queued and the radio isn't keyed
+    Idle,
+    // Audio is queued, waiting for play(). This is also where pause() leaves the rest of a file.
+    Paused,
+    // The radio is keyed but the audio is paused: the lead delay hasn't run out yet, we're in the
+    // hang time, or PTT was keyed by hand with set_ptt()
+    Keyed,
+    // The radio is keyed and the audio is playing
+    Transmitting,
+    // Between the overs of queue_audio_repeated(), un-keying for the gap and keying again (going
+    // back to Transmitting) in time for the next repeat
+    Gap,
+    // The audio has stopped, and the radio is un-keyed once the tail delay is up
+    Unkeying,
+}
+
+// The same names as it's serialised with, e.g. "transmitting"
+impl fmt::Display for PlayerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PlayerState::Idle => "idle",
+            PlayerState::Paused => "paused",
+            PlayerState::Keyed => "keyed",
+            PlayerState::Transmitting => "transmitting",
+            PlayerState::Gap => "gap",
+            PlayerState::Unkeying => "unkeying",
+        })
+    }
+}
+
+// Sent to the channel given to PlayerBuilder::events() as the transmission progresses, so that a
+// UI or transmission log can follow along without polling state()
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlayerEvent {
+    // The radio has been keyed, ahead of the audio
+    Keyed,
+    // The lead delay is up and the audio has started
+    TransmitStarted,
+    // All of the queued audio has been played
+    QueueDrained,
+    // The transmit time limit was hit, see Player::set_max_transmit()
+    TimedOut,
+    // The radio has been un-keyed
+    TransmitEnded,
+    // Something went wrong in the background, where there's no caller to return it to
+    Error(String),
+}
+
+// Where PlayerEvents go, if anywhere. Events are dropped once the receiver has gone away, since
+// there's no sense in stopping a transmission over it.
+#[derive(Clone, Default)]
+struct Events(Option<mpsc::Sender<PlayerEvent>>);
+
+impl Events {
+    fn send(&self, event: PlayerEvent) {
+        if let Some(sender) = &self.0 {
+            let _ = sender.send(event);
         }
+    }
+}
 
-        self.sink.pause();
-        // Sleep for a short period so that audio doesn't get cut off
-        thread::sleep(Duration::from_millis(250));
-        self.toggle_rts()?;
+// The generated tones have to be something that a SineWave can make, and can't clip
+fn check_tone(frequency_hz: f32, amplitude: f32) -> Result<()> {
+    if !(frequency_hz.is_finite() && frequency_hz > 0.0) {
+        return Err(PlayerError::InvalidFrequency(frequency_hz));
+    }
+    if !(0.0..=1.0).contains(&amplitude) {
+        return Err(PlayerError::InvalidAmplitude(amplitude));
+    }
+    Ok(())
+}
 
-        Ok(())
+// NaN would otherwise make it through clamp() and silence everything in a confusing way
+fn clamp_volume(level: f32, max_volume: f32) -> f32 {
+    if level.is_nan() {
+        0.0
+    } else {
+        level.clamp(0.0, max_volume)
+    }
+}
+
+// Failing to open the TTY is reported with its errno, and anything else as it came
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn ptt_open_error(path: &str, e: anyhow::Error) -> PlayerError {
+    #[cfg(unix)]
+    if let Some(&source) = e.downcast_ref::<nix::Error>() {
+        return PlayerError::TtyOpen{path: path.to_string(), source};
+    }
+    PlayerError::PttOpen(e)
+}
+
+// Likewise for a failed ioctl on the TTY
+fn ptt_error(e: anyhow::Error) -> PlayerError {
+    #[cfg(unix)]
+    if let Some(&source) = e.downcast_ref::<nix::Error>() {
+        return PlayerError::Ioctl{what: e.to_string(), source};
     }
+    PlayerError::Ptt(e)
+}
+
+// The radio's keying line, shared between the Player and its background threads. Some interface
+// cables invert the sense of the control line, in which case the radio transmits while the line
+// is *deasserted*, so this is where the logical keyed state is mapped onto the electrical one.
+struct Ptt {
+    control: Mutex<Box<dyn PttControl>>,
+    invert: AtomicBool,
+    log: Option<Mutex<TransmissionLog>>,
+    keyed_periods: Mutex<KeyedPeriods>,
+    // See Player::set_dry_run(). While it's on, 'dry_keyed' stands in for the line.
+    dry_run: AtomicBool,
+    dry_keyed: AtomicBool,
+}
 
-    // We need the *_bad variants here because these are "old"-style syscalls
-    ioctl_read_bad!(tiocmget, IOCTL_TIOCMGET, i32);
-    ioctl_read_bad!(tiocmset, IOCTL_TIOCMSET, i32);
+impl Ptt {
+    fn control(&self) -> MutexGuard<'_, Box<dyn PttControl>> {
+        // Whatever panicked while holding the lock, we still want to be able to release PTT
+        self.control.lock().unwrap_or_else(PoisonError::into_inner)
+    }
 
-    pub fn rts_is_enabled(self: &Player) -> Result<bool> {
-        let mut control_bits:i32 = 0;
+    fn lock_keyed_periods(&self) -> MutexGuard<'_, KeyedPeriods> {
+        self.keyed_periods.lock().unwrap_or_else(PoisonError::into_inner)
+    }
 
-        unsafe { Player::tiocmget(self.tty_fd, &mut control_bits) }
-            .map_err(|e| anyhow!("Failed to get tty parameters: {}", e))?;
-            
-        Ok((control_bits & TIOCM_RTS_FLAG) != 0)
+    fn is_keyed(&self) -> Result<bool> {
+        if self.dry_run.load(Ordering::SeqCst) {
+            return Ok(self.dry_keyed.load(Ordering::SeqCst));
+        }
+        let asserted = self.control().get().map_err(ptt_error)?;
+        Ok(asserted != self.invert.load(Ordering::SeqCst))
     }
 
-    pub fn toggle_rts(self: &Player) -> Result<()> {
-        let mut control_bits:i32 = 0;
+    // Every change of PTT is logged, since that's the record of when we were on the air
+    fn set_keyed(&self, keyed: bool) -> Result<()> {
+        // A dry run never keys the radio, and isn't on the air as far as the duty cycle and the
+        // transmission log are concerned. Releasing is still done for real, in case the dry run
+        // started while the radio was keyed.
+        let dry_run = self.dry_run.load(Ordering::SeqCst);
+        self.dry_keyed.store(keyed && dry_run, Ordering::SeqCst);
+        if keyed && dry_run {
+            info!("PTT keyed (dry run, so not really)");
+            return Ok(());
+        }
 
-        unsafe { Player::tiocmget(self.tty_fd, &mut control_bits) }
-            .map_err(|e| anyhow!("Failed to get tty parameters: {}", e))?;
+        self.control().set(keyed != self.invert.load(Ordering::SeqCst)).map_err(ptt_error)?;
+        info!("PTT {}", if keyed { "keyed" } else { "released" });
 
-        control_bits ^= TIOCM_RTS_FLAG;
+        let mut keyed_periods = self.lock_keyed_periods();
+        match keyed {
+            true => keyed_periods.keyed(Instant::now()),
+            false => keyed_periods.released(Instant::now()),
+        }
+        drop(keyed_periods);
 
-        unsafe { Player::tiocmset(self.tty_fd, &mut control_bits) }
-            .map_err(|e| anyhow!("Failed to set tty parameters: {}", e))?;
-            
+        if let Some(log) = &self.log {
+            let mut log = log.lock().unwrap_or_else(PoisonError::into_inner);
+            if keyed {
+                log.keyed();
+            } else if let Err(e) = log.released() {
+                // The radio's been released, which is what matters
+                error!("{:#}", e);
+            }
+        }
         Ok(())
     }
 }
 
-impl Drop for Player {
-    fn drop(&mut self) {
-        // Because we have a raw FD from nix::fcntl, we need to explicitly close(2) it here in
-        // order to not leak the FD. This is basically an assertion so panicking on failure is
-        // acceptable.
-        nix::unistd::close(self.tty_fd).expect("Failed to close fd");
+// The keying line of the Player registered with install_signal_handler(). This is weak so that the
+// registered Player can still be dropped (releasing its device) as normal.
+static SIGNAL_PTT:Mutex<Option<Weak<Ptt>>> = Mutex::new(None);
+static SIGNAL_HANDLER_INSTALLED:AtomicBool = AtomicBool::new(false);
+
+fn release_ptt_and_exit() {
+    let ptt = SIGNAL_PTT.lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+        .and_then(Weak::upgrade);
+    if let Some(ptt) = ptt {
+        if let Err(e) = ptt.set_keyed(false) {
+            error!("Failed to release PTT: {:#}", e);
+        }
+        // Exiting closes file descriptors anyway, but not every backend is just one of those
+        if let Err(e) = ptt.control().close() {
+            error!("Failed to close PTT device: {:#}", e);
+        }
+    }
+
+    // Exit with the conventional status for being interrupted by Ctrl-C
+    std::process::exit(130);
+}
+
+// Where a transmission started by play() has got to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    Keying,
+    Transmitting,
+    // Between the overs of queue_audio_repeated()
+    Gap,
+    Unkeying,
+    // Keyed for the tail delay and then the hang time after the audio has run out, see
+    // Player::set_hang_time()
+    Hanging,
+}
+
+// When the silence between two overs of queue_audio_repeated() started playing, and how long it is
+type Gap = (Instant, Duration);
+
+// How long the audio has been playing for, going by the clock rather than the sink (which can't
+// say across sources), so it only runs while the sink is playing
+#[derive(Default)]
+struct Stopwatch {
+    elapsed: Duration,
+    since: Option<Instant>,
+}
+
+impl Stopwatch {
+    fn start(&mut self) {
+        self.since.get_or_insert_with(Instant::now);
+    }
+
+    fn stop(&mut self) {
+        self.elapsed = self.read();
+        self.since = None;
+    }
+
+    // Back to zero, still running if it was
+    fn reset(&mut self) {
+        self.elapsed = Duration::ZERO;
+        if self.since.is_some() {
+            self.since = Some(Instant::now());
+        }
+    }
+
+    fn read(&self) -> Duration {
+        self.elapsed + self.since.map_or(Duration::ZERO, |since| since.elapsed())
+    }
+
+    // For a seek from 'from' to 'to' in what's playing, which changes how much has been played
+    // without any time passing
+    fn skip(&mut self, from: Duration, to: Duration) {
+        match to >= from {
+            true => self.elapsed += to - from,
+            false => self.elapsed = self.elapsed.saturating_sub(from - to),
+        }
+    }
+}
+
+// Carries out a transmission started by play() on a thread of its own, so that the caller isn't
+// held up by the delays: it waits out the lead delay, starts the audio, and releases PTT (after
+// the tail delay) once the queued audio has drained, the transmit time limit is exceeded, or
+// release() is called, un-keying in between for any gaps between repeats that ask for it.
+// Dropping 'release' instead makes the thread exit without touching the radio again. It also
+// carries out test_key(), which is the same without any audio, see TransmissionThread::hold().
+struct Transmission {
+    release: mpsc::Sender<()>,
+    phase: Arc<Mutex<Phase>>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Transmission {
+    // None once the thread is done with the radio
+    fn phase(&self) -> Option<Phase> {
+        if self.thread.is_finished() {
+            None
+        } else {
+            Some(*self.phase.lock().unwrap_or_else(PoisonError::into_inner))
+        }
+    }
+
+    fn release(&self) {
+        // If the thread has already finished, PTT has already been released
+        let _ = self.release.send(());
+    }
+
+    // Carries on transmitting whatever's been queued since, if we're still within the hang time.
+    // The thread notices on its next look.
+    fn resume(&self) -> bool {
+        let mut phase = self.phase.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.thread.is_finished() || *phase != Phase::Hanging {
+            return false;
+        }
+        *phase = Phase::Transmitting;
+        true
+    }
+
+    // Waits for the transmission to end by itself
+    fn finish(self) {
+        // The thread only panics if the sink does, there's nothing useful to do about that here
+        let _ = self.thread.join();
+    }
+
+    // Stops the transmission where it is. If it's past the audio, this waits for PTT to be released.
+    fn cancel(self) {
+        drop(self.release);
+        let _ = self.thread.join();
+    }
+}
+
+enum Wait {
+    Elapsed,
+    Released,
+    Cancelled,
+}
+
+// How a transmission left off, when it didn't make it to the end of the audio
+enum Ending {
+    // All of the audio has been played, so there's the hang time before PTT is released
+    Drained,
+    // PTT still needs releasing
+    Unkey,
+    // The radio isn't keyed anymore, or we've been told to leave it alone
+    Done,
+}
+
+// The CTCSS tone, which goes out on a sink of its own from when the radio is keyed until it's
+// un-keyed, so that a repeater has decoded it before the audio starts and doesn't drop out in the
+// tail or the hang time
+struct Ctcss {
+    sink: Arc<dyn AudioSink>,
+    frequency_hz: f32,
+    sample_rate: u32,
+    // See Player::ctcss_phase
+    phase: Arc<Mutex<f32>>,
+}
+
+impl Ctcss {
+    fn start(&self) {
+        self.sink.clear();
+        self.sink.append(audio::tone(self.frequency_hz, CTCSS_LEVEL, self.sample_rate, Arc::clone(&self.phase)));
+        self.sink.play();
+    }
+
+    fn stop(&self) {
+        self.sink.clear();
     }
 }
 
+// The side of a Transmission that runs on its thread
+struct TransmissionThread {
+    released: mpsc::Receiver<()>,
+    phase: Arc<Mutex<Phase>>,
+    sink: Arc<dyn AudioSink>,
+    courtesy_sink: Option<Arc<dyn AudioSink>>,
+    ctcss: Option<Ctcss>,
+    ptt: Arc<Ptt>,
+    timed_out: Arc<AtomicBool>,
+    next_gap: Arc<Mutex<Option<Gap>>>,
+    played: Arc<Mutex<Stopwatch>>,
+    lead_delay: Duration,
+    tail_delay: Duration,
+    hang_time: Duration,
+    max_transmit: Option<Duration>,
+    events: Events,
+}
+
+impl TransmissionThread {
+    fn run(self) {
+        let keyed_at = Instant::now();
+        // The radio was keyed just before we started
+        self.set_tone(true);
+
+        // Give the radio time to switch to transmit so that the start of the audio doesn't get
+        // cut off. If we're released in the meantime, the audio never starts at all.
+        let mut ending = match self.wait_until(keyed_at + self.lead_delay) {
+            Wait::Elapsed => self.transmit(keyed_at),
+            Wait::Released => Ending::Unkey,
+            Wait::Cancelled => Ending::Done,
+        };
+        loop {
+            if let Ending::Done = ending {
+                self.set_tone(false);
+                return;
+            }
+
+            // Pausing lets the next play() pick up from here, whether we ran out of audio or time
+            // or were paused. Then let the tail of the audio make it out over the air before the
+            // radio switches to receive. Having run out of audio, play() can carry the
+            // transmission on from as soon as it did, so the tail delay is part of the hang time.
+            let hanging = matches!(ending, Ending::Drained) && !self.hang_time.is_zero();
+            self.set_phase(if hanging { Phase::Hanging } else { Phase::Unkeying });
+            self.pause_audio();
+            // Including the roger beep that pause() might have started
+            if let Some(courtesy_sink) = &self.courtesy_sink {
+                courtesy_sink.sleep_until_end();
+            }
+
+            // Keyed all along, so the time limit still counts from the start
+            ending = match hanging {
+                true => match self.hang() {
+                    None => self.transmit(keyed_at),
+                    Some(ending) => ending,
+                },
+                false => {
+                    thread::sleep(self.tail_delay);
+                    Ending::Unkey
+                }
+            };
+            if let Ending::Unkey = ending {
+                self.set_keyed(false);
+                return;
+            }
+        }
+    }
+
+    // Keeps the radio keyed for 'duration' with nothing going to the sink, for Player::test_key().
+    // The phase stays at Keying throughout, so that pause() un-keys without a roger beep.
+    fn hold(self, duration: Duration) {
+        match self.wait_until(Instant::now() + duration) {
+            Wait::Elapsed | Wait::Released => (),
+            Wait::Cancelled => return,
+        }
+        for attempt in 1..=TEST_KEY_RELEASE_ATTEMPTS {
+            match self.ptt.set_keyed(false) {
+                Ok(()) => {
+                    self.events.send(PlayerEvent::TransmitEnded);
+                    return;
+                }
+                Err(e) => {
+                    error!("Failed to release PTT after the test key (attempt {} of {}): {:#}", attempt, TEST_KEY_RELEASE_ATTEMPTS, e);
+                    thread::sleep(TRANSMISSION_POLL_INTERVAL);
+                }
+            }
+        }
+        error!("Gave up releasing PTT after the test key, the radio is still keyed");
+        self.events.send(PlayerEvent::Error("Failed to release PTT after the test key".to_string()));
+    }
+
+    // Stays keyed for the tail delay and the hang time, in case play() is called again before
+    // they're up. Returns None if it is, to carry on transmitting.
+    fn hang(&self) -> Option<Ending> {
+        let deadline = Instant::now() + self.tail_delay + self.hang_time;
+        loop {
+            match self.wait_until(deadline.min(Instant::now() + TRANSMISSION_POLL_INTERVAL)) {
+                Wait::Elapsed => (),
+                Wait::Released => return Some(Ending::Unkey),
+                Wait::Cancelled => return Some(Ending::Done),
+            }
+
+            // Under the lock, so that play() can't resume us just as we give up
+            let mut phase = self.phase.lock().unwrap_or_else(PoisonError::into_inner);
+            if *phase != Phase::Hanging {
+                return None;
+            }
+            if Instant::now() >= deadline {
+                *phase = Phase::Unkeying;
+                return Some(Ending::Unkey);
+            }
+        }
+    }
+
+    fn transmit(&self, mut keyed_at: Instant) -> Ending {
+        self.sink.play();
+        self.lock_played().start();
+        self.set_phase(Phase::Transmitting);
+        self.events.send(PlayerEvent::TransmitStarted);
+
+        loop {
+            match self.wait_until(Instant::now() + TRANSMISSION_POLL_INTERVAL) {
+                Wait::Elapsed => (),
+                Wait::Released => return Ending::Unkey,
+                Wait::Cancelled => return Ending::Done,
+            }
+
+            let gap = self.next_gap.lock().unwrap_or_else(PoisonError::into_inner).take();
+            if let Some(gap) = gap {
+                if let Some(ending) = self.sit_out_gap(gap) {
+                    return ending;
+                }
+                // The time limit is for each over on its own
+                keyed_at = Instant::now();
+            }
+
+            if self.sink.is_empty() {
+                self.events.send(PlayerEvent::QueueDrained);
+                return Ending::Drained;
+            }
+            if let Some(limit) = self.max_transmit.filter(|&limit| keyed_at.elapsed() >= limit) {
+                warn!("Transmitting for longer than the limit of {:?}, releasing PTT", limit);
+                self.timed_out.store(true, Ordering::SeqCst);
+                self.events.send(PlayerEvent::TimedOut);
+                return Ending::Unkey;
+            }
+        }
+    }
+
+    // Un-keys for the silence between two overs, keying again in time for the lead delay to be
+    // up when the next one starts. Returns None to carry on transmitting.
+    fn sit_out_gap(&self, (started, gap): Gap) -> Option<Ending> {
+        // Not worth it if it won't fit both delays (which could have changed since it was queued)
+        if gap < self.tail_delay + self.lead_delay {
+            return None;
+        }
+
+        self.set_phase(Phase::Gap);
+        match self.wait_until(started + self.tail_delay) {
+            Wait::Elapsed => (),
+            Wait::Released => return Some(Ending::Unkey),
+            Wait::Cancelled => return Some(Ending::Done),
+        }
+        self.set_keyed(false);
+
+        match self.wait_until(started + gap - self.lead_delay) {
+            Wait::Elapsed => (),
+            Wait::Released => {
+                self.pause_audio();
+                return Some(Ending::Done);
+            }
+            Wait::Cancelled => return Some(Ending::Done),
+        }
+        self.set_phase(Phase::Keying);
+        self.set_keyed(true);
+
+        match self.wait_until(started + gap) {
+            Wait::Elapsed => (),
+            Wait::Released => return Some(Ending::Unkey),
+            Wait::Cancelled => return Some(Ending::Done),
+        }
+        self.set_phase(Phase::Transmitting);
+        self.events.send(PlayerEvent::TransmitStarted);
+
+        None
+    }
+
+    fn wait_until(&self, deadline: Instant) -> Wait {
+        match self.released.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Err(mpsc::RecvTimeoutError::Timeout) => Wait::Elapsed,
+            Ok(()) => Wait::Released,
+            Err(mpsc::RecvTimeoutError::Disconnected) => Wait::Cancelled,
+        }
+    }
+
+    fn set_phase(&self, phase: Phase) {
+        *self.phase.lock().unwrap_or_else(PoisonError::into_inner) = phase;
+    }
+
+    fn lock_played(&self) -> MutexGuard<'_, Stopwatch> {
+        self.played.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn pause_audio(&self) {
+        self.sink.pause();
+        self.lock_played().stop();
+    }
+
+    fn set_tone(&self, on: bool) {
+        match &self.ctcss {
+            Some(ctcss) if on => ctcss.start(),
+            Some(ctcss) => ctcss.stop(),
+            None => (),
+        }
+    }
+
+    // There's nobody to return errors to from here, so they're reported as events instead
+    fn set_keyed(&self, keyed: bool) {
+        self.set_tone(keyed);
+        match self.ptt.set_keyed(keyed) {
+            Ok(()) => self.events.send(if keyed { PlayerEvent::Keyed } else { PlayerEvent::TransmitEnded }),
+            Err(e) => {
+                let action = if keyed { "key" } else { "release" };
+                error!("Failed to {} PTT during the transmission: {:#}", action, e);
+                self.events.send(PlayerEvent::Error(format!("Failed to {} PTT: {:#}", action, e)));
+            }
+        }
+    }
+}
+
+// What the audio device was opened with. rodio converts everything to this on the way out.
+#[derive(Clone, Copy, Debug)]
+struct OutputFormat {
+    channels: u16,
+    sample_rate: u32,
+}
+
+// A guess for if the device won't say, which can't really happen since it just has
+impl Default for OutputFormat {
+    fn default() -> OutputFormat {
+        OutputFormat{channels: 2, sample_rate: 44100}
+    }
+}
+
+// How the audio device to open is picked out from the ones there are
+enum AudioDevice {
+    // Its exact name, or failing that a unique part of it, ignoring case
+    Name(String),
+    // The one name this matches
+    Pattern(Regex),
+}
+
+impl AudioDevice {
+    // Which of 'names' this picks out, or why it can't pick out just one
+    fn choose(&self, names: &[String]) -> Result<usize> {
+        let (wanted, matches):(&str, Vec<usize>) = match self {
+            AudioDevice::Name(name) => {
+                if let Some(i) = names.iter().position(|n| n == name) {
+                    return Ok(i);
+                }
+                let lowercase = name.to_lowercase();
+                (name, (0..names.len()).filter(|&i| names[i].to_lowercase().contains(&lowercase)).collect())
+            }
+            AudioDevice::Pattern(pattern) => {
+                (pattern.as_str(), (0..names.len()).filter(|&i| pattern.is_match(&names[i])).collect())
+            }
+        };
+        match matches[..] {
+            [i] => Ok(i),
+            [] => Err(PlayerError::DeviceNotFound{name: wanted.to_string(), available: names.to_vec()}),
+            _ => Err(PlayerError::AmbiguousDevice{
+                name: wanted.to_string(),
+                matches: matches.into_iter().map(|i| names[i].clone()).collect(),
+            }),
+        }
+    }
+}
+
+// The Player's sink, and its courtesy and tone sinks which are mixed in with it
+type OutputSinks = (Arc<Sink>, Arc<Sink>, Arc<Sink>);
+
+// cpal streams can't be moved between threads, so the OutputStream is opened on a thread of its
+// own and kept there for as long as the Player is around. That way the Player itself can be Send
+// and Sync, and only the Sinks (which are both) are shared with it. Dropping this closes the
+// stream.
+struct AudioOutput {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl AudioOutput {
+    // Also returns the format that the device is being driven in
+    fn open(audio_device: AudioDevice) -> Result<(AudioOutput, OutputSinks, OutputFormat)> {
+        let (opened_tx, opened) = mpsc::channel();
+        let (stop, stopped) = mpsc::channel::<()>();
+
+        let thread = thread::spawn(move || {
+            let output = Player::find_output_device(&audio_device).and_then(|output_dev| {
+                // If 'stream' is dropped, the stream_handle and sink are useless. See this note
+                // from the rodio documentation:
+                //   > If [the OutputStream] is dropped playback will end [and] attached OutputStreamHandles will no longer work.
+                let (stream, stream_handle) = OutputStream::try_from_device(&output_dev)?;
+                let sinks = (Sink::try_new(&stream_handle)?, Sink::try_new(&stream_handle)?, Sink::try_new(&stream_handle)?);
+                // This is the config that the stream was just opened with
+                let format = output_dev.default_output_config().map_or(OutputFormat::default(), |config| {
+                    OutputFormat{channels: config.channels(), sample_rate: config.sample_rate().0}
+                });
+                Ok((stream, sinks, format))
+            });
+
+            match output {
+                Ok((stream, (sink, courtesy_sink, tone_sink), format)) => {
+                    let _ = opened_tx.send(Ok(((Arc::new(sink), Arc::new(courtesy_sink), Arc::new(tone_sink)), format)));
+                    // Hold on to the stream until the Player goes away
+                    let _ = stopped.recv();
+                    drop(stream);
+                }
+                Err(e) => {
+                    let _ = opened_tx.send(Err(e));
+                }
+            }
+        });
+
+        let (sinks, format) = opened.recv().expect("Audio output thread exited without opening the stream")?;
+        Ok((AudioOutput{stop: Some(stop), thread: Some(thread)}, sinks, format))
+    }
+}
+
+impl Drop for AudioOutput {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+pub struct Player {
+    ptt: Arc<Ptt>,
+    lead_delay: Duration,
+    tail_delay: Duration,
+    hang_time: Duration,
+    max_transmit: Option<Duration>,
+    duty_cycle_limit: Option<DutyCycleLimit>,
+    require_clear_channel: bool,
+    clear_hold: Duration,
+    roger_beep: Option<RogerBeep>,
+    auto_id: Option<Arc<AutoId>>,
+    events: Events,
+    transmission: Mutex<Option<Transmission>>,
+    timed_out: Arc<AtomicBool>,
+    // What each of the sources queued on 'sink' is, see Player::append()
+    queued: Mutex<QueueLog>,
+    passband: Passband,
+    downmix: bool,
+    // None when the Player was given an AudioSink to play into, since we can't know what that is
+    output_format: Option<OutputFormat>,
+    normalization: Option<Normalization>,
+    limiter: Option<f32>,
+    ctcss_hz: Option<f32>,
+    #[cfg(feature = "tts")]
+    tts: crate::tts::TtsEngine,
+    // Where the CTCSS tone got to when it last stopped, see audio::with_tone()
+    ctcss_phase: Arc<Mutex<f32>>,
+    file_gap: Duration,
+    // None for the default, which depends on the delays, see fades()
+    fade_in: Option<Duration>,
+    fade_out: Option<Duration>,
+    max_volume: f32,
+    // Set from the audio thread as each gap queued by queue_audio_repeated() starts playing
+    next_gap: Arc<Mutex<Option<Gap>>>,
+    // How long the audio queued since the queue was last empty has been playing, see position()
+    played: Arc<Mutex<Stopwatch>>,
+    // Held for the whole of play(), pause() and friends, so that calls from different threads
+    // take turns instead of racing each other (and the delays) for the PTT line
+    transition: Mutex<()>,
+    closed: bool,
+
+    sink: Arc<dyn AudioSink>,
+    // Where the roger beep goes when a transmission is paused, since 'sink' still has the rest of
+    // the audio queued ahead of it
+    courtesy_sink: Option<Arc<dyn AudioSink>>,
+    tone_sink: Option<Arc<dyn AudioSink>>,
+    // 'output' must have the same lifetime as 'sink', or audio playback will be halted when it's
+    // dropped. There's no output of our own if the sink was handed to us.
+    #[allow(dead_code)]
+    output: Option<AudioOutput>,
+}
+
+// A Player is meant to be shared with (or handed to) other threads, e.g. a web handler that
+// starts and stops transmissions, so make sure it stays that way
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Player>();
+};
+
+impl Player {
+    // Digirig always appears with CARD=Device in the name, and that appears to be unique to
+    // usb-attached sound devices:
+    // # Device: sysdefault:CARD=Device
+    // # Device: front:CARD=Device,DEV=0
+    // # Device: surround40:CARD=Device,DEV=0
+    // # Device: iec958:CARD=Device,DEV=0
+    //
+    //
+    pub fn for_devices(tty_path: String, audio_device_name: String) -> Result<Player> {
+        Player::for_devices_with_ptt(tty_path, audio_device_name, PttLine::default())
+    }
+
+    // Picks out the audio device with a regular expression instead of its name, e.g. "CARD=Device"
+    // for a Digirig whose DEV number moves around depending on what else is plugged in. Exactly
+    // one device has to match.
+    pub fn for_devices_matching(tty_path: String, audio_device_pattern: &str) -> Result<Player> {
+        let pattern = Regex::new(audio_device_pattern)
+            .map_err(|source| PlayerError::DevicePattern{pattern: audio_device_pattern.to_string(), source})?;
+        Player::builder()
+            .tty_path(tty_path)
+            .audio_device_matching(pattern)
+            .build()
+    }
+
+    pub fn for_devices_with_ptt(tty_path: String, audio_device_name: String, ptt_line: PttLine) -> Result<Player> {
+        Player::builder()
+            .tty_path(tty_path)
+            .audio_device(audio_device_name)
+            .ptt_line(ptt_line)
+            .build()
+    }
+
+    // Keys the radio through a TTY that's already open (see ptt::TtyPtt::with_fd()), which the
+    // Player then owns and closes
+    #[cfg(unix)]
+    pub fn with_fd(fd: RawFd, audio_device_name: String, ptt_line: PttLine) -> Result<Player> {
+        Player::builder()
+            .ptt_control(Box::new(ptt::TtyPtt::with_fd(fd, ptt_line)))
+            .audio_device(audio_device_name)
+            .build()
+    }
+
+    // Keys the radio with a Raspberry Pi GPIO pin (by BCM number) instead of a serial port, for
+    // headless setups without a USB serial adapter. See ptt::GpioPtt.
+    #[cfg(feature = "gpio")]
+    pub fn for_gpio_ptt(audio_device_name: String, bcm_pin: u8, active_high: bool) -> Result<Player> {
+        let control = ptt::GpioPtt::open(bcm_pin, active_high).map_err(PlayerError::PttOpen)?;
+        Player::builder()
+            .ptt_control(Box::new(control))
+            .audio_device(audio_device_name)
+            .build()
+    }
+
+    // Finds a Digirig and opens it, instead of needing its TTY and sound card to be spelled out.
+    // Use autodetect_serial() to pick one if there's more than one plugged in.
+    pub fn autodetect() -> Result<Player> {
+        let digirig = crate::autodetect::find_digirig(None).map_err(PlayerError::Autodetect)?;
+        Player::for_devices(digirig.tty_path, digirig.audio_device)
+    }
+
+    pub fn autodetect_serial(serial_number: &str) -> Result<Player> {
+        let digirig = crate::autodetect::find_digirig(Some(serial_number)).map_err(PlayerError::Autodetect)?;
+        Player::for_devices(digirig.tty_path, digirig.audio_device)
+    }
+
+    // Builds a Player from a station profile. See config::Config for the format.
+    pub fn from_config(path: &Path) -> Result<Player> {
+        Config::load(path)
+            .and_then(|config| config.builder())
+            .map_err(PlayerError::Config)?
+            .build()
+    }
+
+    pub fn builder() -> PlayerBuilder {
+        PlayerBuilder::default()
+    }
+
+    // Names of all the audio output devices that can be passed to for_devices(), so that a
+    // front-end can offer a picker rather than making users run 'aplay -L'
+    pub fn list_output_devices() -> Result<Vec<String>> {
+        Ok(Player::output_devices()?
+            .filter_map(|dev| dev.name().ok())
+            .collect())
+    }
+
+    // ALSA names are long and shift around between systems, so besides an exact match this accepts
+    // any unambiguous, case-insensitive part of a name (e.g. "CARD=Device" for the Digirig)
+    fn find_output_device(audio_device: &AudioDevice) -> Result<rodio::Device> {
+        // List output devices and find our target device
+        let (names, mut devices):(Vec<String>, Vec<rodio::Device>) = Player::output_devices()?
+            .filter_map(|dev| Some((dev.name().ok()?, dev)))
+            .unzip();
+        let i = audio_device.choose(&names)?;
+        info!("Using audio device '{}'", names[i]);
+        Ok(devices.swap_remove(i))
+    }
+
+    fn output_devices() -> Result<impl Iterator<Item = rodio::Device>> {
+        Ok(cpal::default_host().output_devices()?)
+    }
+
+    // The lead and tail delays are independent: many radios need a long lead-in to finish T/R
+    // switching before the first syllable, but only a short tail once the audio has stopped.
+    pub fn set_lead_delay(self: &mut Player, delay: Duration) {
+        self.lead_delay = delay;
+    }
+
+    pub fn set_tail_delay(self: &mut Player, delay: Duration) {
+        self.tail_delay = delay;
+    }
+
+    // Keeps the radio keyed for 'hang_time' after the audio runs out (on top of the tail delay),
+    // like a repeater's hang time: if play() is called again before it's up, for whatever's been
+    // queued since, the radio is never un-keyed in between. That spares the T/R relay (and the
+    // lead delay) on a run of short transmissions. pause() and stop() still un-key straight away,
+    // and wait_until_empty() waits out the hang time too. Zero, the default, turns it off.
+    pub fn set_hang_time(self: &mut Player, hang_time: Duration) {
+        self.hang_time = hang_time;
+    }
+
+    // Plays everything as usual without ever keying the radio, e.g. for setting levels and filters
+    // with the audio going to a local sound card. Everything else (the delays, the state, the
+    // events) carries on as though the radio was keyed. Turning it on releases PTT if it's keyed;
+    // turning it off mid-transmission leaves the radio un-keyed until the next one.
+    pub fn set_dry_run(self: &Player, dry_run: bool) -> Result<()> {
+        let was_keyed = self.ptt_is_enabled()?;
+        self.ptt.dry_run.store(dry_run, Ordering::SeqCst);
+        if dry_run && was_keyed {
+            self.ptt.set_keyed(false)?;
+            self.ptt.dry_keyed.store(true, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    pub fn is_dry_run(self: &Player) -> bool {
+        self.ptt.dry_run.load(Ordering::SeqCst)
+    }
+
+    // Flipping the polarity also flips the logical state of the line, so make sure that doesn't
+    // leave the radio keyed while we're not transmitting
+    pub fn set_invert_ptt(self: &mut Player, invert: bool) -> Result<()> {
+        self.ptt.invert.store(invert, Ordering::SeqCst);
+        if self.sink.is_paused() {
+            self.set_ptt(false)?;
+        }
+
+        Ok(())
+    }
+
+    // Installs SIGINT/SIGTERM handlers that release PTT before exiting, so that hitting Ctrl-C
+    // mid-transmission doesn't leave the radio keyed. Only one Player can be registered at a time;
+    // installing the handler for another Player replaces this one.
+    pub fn install_signal_handler(self: &Player) -> Result<()> {
+        *SIGNAL_PTT.lock().unwrap_or_else(PoisonError::into_inner) = Some(Arc::downgrade(&self.ptt));
+
+        if !SIGNAL_HANDLER_INSTALLED.swap(true, Ordering::SeqCst) {
+            if let Err(e) = ctrlc::set_handler(release_ptt_and_exit) {
+                SIGNAL_HANDLER_INSTALLED.store(false, Ordering::SeqCst);
+                return Err(e.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Upper bound on how long the radio may stay keyed by a single play(). If it's exceeded, the
+    // audio is paused, PTT is released, and further calls to play() fail until clear_timeout()
+    // is called, so a stuck transmitter can't hold the channel indefinitely.
+    pub fn set_max_transmit(self: &mut Player, limit: Option<Duration>) {
+        self.max_transmit = limit;
+    }
+
+    pub fn timed_out(self: &Player) -> bool {
+        self.timed_out.load(Ordering::SeqCst)
+    }
+
+    pub fn clear_timeout(self: &Player) {
+        self.timed_out.store(false, Ordering::SeqCst);
+    }
+
+    // The fraction of the last 'window' (up to MAX_DUTY_CYCLE_WINDOW) that the radio has been
+    // keyed for, however it was keyed, from 0.0 to 1.0
+    pub fn duty_cycle(self: &Player, window: Duration) -> f32 {
+        if window.is_zero() {
+            return 0.0;
+        }
+        let keyed = self.ptt.lock_keyed_periods().keyed_time(window, Instant::now());
+        keyed.as_secs_f32() / window.as_secs_f32()
+    }
+
+    // See DutyCycleLimit. A transmission is taken to last as long as what's queued (when that's
+    // known, see progress()) plus the delays, and to come on top of everything already in the
+    // window, which errs on the safe side.
+    pub fn set_duty_cycle_limit(self: &mut Player, limit: Option<DutyCycleLimit>) {
+        self.duty_cycle_limit = limit;
+    }
+
+    fn check_duty_cycle(self: &Player) -> Result<()> {
+        let total = self.lock_queued().total_duration();
+        let queued = total.map_or(Duration::ZERO, |total| total.saturating_sub(self.position().unwrap_or(Duration::ZERO)));
+        self.check_duty_cycle_for(queued + self.lead_delay + self.tail_delay)
+    }
+
+    // Whether keying for 'keyed_for' more would go over the duty cycle limit
+    fn check_duty_cycle_for(self: &Player, keyed_for: Duration) -> Result<()> {
+        let Some(limit) = self.duty_cycle_limit.filter(|limit| !limit.window.is_zero()) else {
+            return Ok(());
+        };
+        let keyed = self.ptt.lock_keyed_periods().keyed_time(limit.window, Instant::now()) + keyed_for;
+        let duty = keyed.as_secs_f32() / limit.window.as_secs_f32();
+        if duty > limit.max {
+            return Err(PlayerError::DutyCycleExceeded{duty, limit: limit.max, window: limit.window});
+        }
+        Ok(())
+    }
+
+    // Listen before transmit: when set, play() refuses to key the radio (returning ChannelBusy)
+    // while channel_is_busy() reports a carrier
+    pub fn set_require_clear_channel(self: &mut Player, required: bool) {
+        self.require_clear_channel = required;
+    }
+
+    // How long play_when_clear() waits for the channel to stay quiet before it transmits, so
+    // that we don't key up in the middle of a QSO just because someone paused for breath
+    pub fn set_clear_hold(self: &mut Player, hold: Duration) {
+        self.clear_hold = hold;
+    }
+
+    // Whether someone else is transmitting on the channel, going by the radio's carrier detect
+    pub fn channel_is_busy(self: &Player) -> Result<bool> {
+        self.ptt.control().carrier_detect().map_err(ptt_error)
+    }
+
+    // Drives any modem control line of the PTT device directly, electrically (i.e. ignoring
+    // set_invert_ptt()), for cables that use the other lines for something besides keying
+    pub fn set_line(self: &Player, line: ModemLine, asserted: bool) -> Result<()> {
+        self.ptt.control().set_line(line, asserted).map_err(ptt_error)
+    }
+
+    pub fn get_line(self: &Player, line: ModemLine) -> Result<bool> {
+        self.ptt.control().get_line(line).map_err(ptt_error)
+    }
+
+    // Every modem control line of the PTT device, electrically (i.e. ignoring set_invert_ptt())
+    pub fn modem_status(self: &Player) -> Result<ModemStatus> {
+        self.ptt.control().modem_status().map_err(ptt_error)
+    }
+
+    // When set, a roger beep is sent at the end of every over, while the radio is still keyed
+    // rather than after the transmission has ended: after the last of the queued audio, or when
+    // pause() cuts it short
+    pub fn set_roger_beep(self: &mut Player, beep: Option<RogerBeep>) {
+        self.roger_beep = beep;
+    }
+
+    // Sends the station's callsign in Morse code ahead of the next file to be played whenever
+    // 'interval' has gone by since it was last sent (and ahead of the first one). Only files
+    // queued from now on get a chance at it.
+    pub fn set_auto_id(self: &mut Player, auto_id: Option<AutoIdConfig>) -> Result<()> {
+        self.auto_id = auto_id.map(AutoId::new).transpose()?.map(Arc::new);
+        Ok(())
+    }
+
+    // Mixes every file queued from now on down to mono, see audio::downmix()
+    pub fn set_downmix(self: &mut Player, downmix: bool) {
+        self.downmix = downmix;
+    }
+
+    // Filters every file queued from now on, see audio::band_limit()
+    pub fn set_passband(self: &mut Player, passband: Passband) {
+        self.passband = passband;
+    }
+
+    // Sends a sub-audible CTCSS tone (e.g. 100.0Hz) with every transmission from now on, for
+    // repeaters that won't open without one. It goes out on the tone sink for the whole time the
+    // radio is keyed, lead and tail delays and hang time included, and everything queued from
+    // now on is turned down to make room for it. A Player given an 'audio_sink' without a
+    // 'tone_sink' can only mix the tone into the audio, so there it's missing from the delays.
+    pub fn set_ctcss(self: &mut Player, frequency_hz: Option<f32>) {
+        self.ctcss_hz = frequency_hz;
+    }
+
+    // Brings every file queued from now on to the same level, see audio::normalize()
+    pub fn set_normalization(self: &mut Player, normalization: Option<Normalization>) {
+        self.normalization = normalization;
+    }
+
+    // The engine that queue_tts() has speak the text, espeak-ng unless told otherwise
+    #[cfg(feature = "tts")]
+    pub fn set_tts_engine(self: &mut Player, engine: crate::tts::TtsEngine) {
+        self.tts = engine;
+    }
+
+    // Soft-limits the peaks of every file queued from now on to full scale, starting at
+    // 'threshold' (a fraction of full scale, e.g. 0.8), see audio::limit(). None turns it off.
+    pub fn set_limiter(self: &mut Player, threshold: Option<f32>) {
+        self.limiter = threshold;
+    }
+
+    // Fades every file queued from now on in and out over the given times, so that the audio
+    // doesn't start or stop with a thump, instead of over a tenth of the lead and tail delays
+    // (up to 50ms). See audio::fade().
+    pub fn set_fades(self: &mut Player, fade_in: Duration, fade_out: Duration) {
+        self.fade_in = Some(fade_in);
+        self.fade_out = Some(fade_out);
+    }
+
+    // The times that files are faded in and out over
+    pub fn fades(self: &Player) -> (Duration, Duration) {
+        let default = |delay: Duration| (delay / DEFAULT_FADE_DIVISOR).min(MAX_DEFAULT_FADE);
+        (self.fade_in.unwrap_or_else(|| default(self.lead_delay)),
+         self.fade_out.unwrap_or_else(|| default(self.tail_delay)))
+    }
+
+    // Silence left between the files of queue_many(), with the radio still keyed
+    pub fn set_file_gap(self: &mut Player, gap: Duration) {
+        self.file_gap = gap;
+    }
+
+    // Output level applied to all audio. 1.0 is unity gain, values below that attenuate and values
+    // above amplify. Most rigs' mic inputs want this well below unity to keep deviation in check.
+    // The level is clamped to between 0 and the ceiling set with set_max_volume().
+    pub fn set_volume(self: &Player, level: f32) {
+        self.sink.set_volume(clamp_volume(level, self.max_volume));
+    }
+
+    // A ceiling on set_volume(), so that a slip of the finger can't over-deviate the transmitter.
+    // Lowering it below the current volume turns the volume down to match.
+    pub fn set_max_volume(self: &mut Player, max_volume: f32) {
+        self.max_volume = clamp_volume(max_volume, MAX_VOLUME);
+        self.set_volume(self.volume());
+    }
+
+    pub fn volume(self: &Player) -> f32 {
+        self.sink.volume()
+    }
+
+    pub fn queue_audio(self: &Player, audiofile_path: String) -> Result<()> {
+        self.queue_file(audiofile_path, None)
+    }
+
+    // queue_audio(), with a name for current_item() and remaining() to give it, e.g. "the weather"
+    pub fn queue_audio_labelled(self: &Player, audiofile_path: String, label: String) -> Result<()> {
+        self.queue_file(audiofile_path, Some(label))
+    }
+
+    fn queue_file(self: &Player, audiofile_path: String, label: Option<String>) -> Result<()> {
+        let source = self.load_file(&audiofile_path)?;
+
+        match &label {
+            Some(label) => info!("Queued audio file {} ({})", audiofile_path, label),
+            None => info!("Queued audio file {}", audiofile_path),
+        }
+        self.append_labelled(Queued::File(audiofile_path), label, source);
+        self.append_roger_beep();
+        self.sink.pause();
+
+        Ok(())
+    }
+
+    // Queues audio from anything that can be read, e.g. a Cursor over audio generated in memory,
+    // a file that's already open or a decompressor, in any format that a file could be in. The
+    // decoder needs to seek, so a pipe like stdin has to be read into a Cursor<Vec<u8>> first.
+    // Normalisation (if it's on) has to see all of the audio up front, so with it this reads
+    // 'reader' to the end before returning.
+    //
+    // 'reader' is read from the audio thread for as long as the audio plays, long after this has
+    // returned, hence 'static and Send; rodio's decoders ask for Sync on top of that.
+    pub fn queue_reader<R: Read + Seek + Send + Sync + 'static>(self: &Player, reader: R) -> Result<()> {
+        self.queue_stream(Decoder::new(reader))
+    }
+
+    // Queues audio that's already in memory, e.g. a clip that's just been downloaded, without
+    // going through a file. The source owns 'data' from here on. 'format_hint' says what format
+    // it's in, if that's known; otherwise it's worked out from the data.
+    pub fn queue_bytes(self: &Player, data: Vec<u8>, format_hint: Option<AudioFormat>) -> Result<()> {
+        let reader = Cursor::new(data);
+        self.queue_stream(match format_hint {
+            Some(format) => format.decoder(reader),
+            None => Decoder::new(reader),
+        })
+    }
+
+    // Queues audio piped in on stdin, e.g. from "espeak-ng --stdout ... | rplayer -". stdin can't
+    // be seeked in, so it's read to the end (into memory) first, which means this doesn't return
+    // until whatever's writing to it has finished and closed it.
+    pub fn queue_stdin(self: &Player) -> Result<()> {
+        let mut data = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut data).map_err(PlayerError::Stdin)?;
+        info!("Read {} bytes of audio from stdin", data.len());
+        self.queue_bytes(data, None)
+    }
+
+    fn queue_stream<R: Read + Seek + Send + Sync + 'static>(self: &Player, decoded: std::result::Result<Decoder<R>, DecoderError>) -> Result<()> {
+        let source = decoded.map_err(PlayerError::DecodeStream)?;
+        let source = self.process(Box::new(source.convert_samples()), "the audio stream")?;
+
+        info!("Queued audio from a stream");
+        self.append(Queued::Stream, source);
+        self.append_roger_beep();
+        self.sink.pause();
+
+        Ok(())
+    }
+
+    // Queues 'text' as spoken by the text-to-speech engine (see set_tts_engine()), e.g. for an
+    // announcement that changes too often to record. The engine runs before this returns, and its
+    // output is processed like any file.
+    #[cfg(feature = "tts")]
+    pub fn queue_tts(self: &Player, text: &str) -> Result<()> {
+        let wav = self.tts.synthesize(text).map_err(PlayerError::Tts)?;
+        let what = Queued::Speech(text.to_string());
+        let source = Decoder::new(Cursor::new(wav)).map_err(PlayerError::DecodeStream)?;
+        let source = self.process(Box::new(source.convert_samples()), &what.describe())?;
+
+        info!("Queued {}", what.describe());
+        self.append(what, source);
+        self.append_roger_beep();
+        self.sink.pause();
+
+        Ok(())
+    }
+
+    // Queues several files to be sent as a single transmission (e.g. the parts of a bulletin), so
+    // that one play() keys the radio for all of them. They're separated by the gap set with
+    // set_file_gap(), if any. Every file is decoded up front, so nothing is queued if any of them
+    // can't be played.
+    pub fn queue_many(self: &Player, audiofile_paths: &[String]) -> Result<()> {
+        let sources = audiofile_paths.iter()
+            .map(|path| self.load_file(path))
+            .collect::<Result<Vec<_>>>()?;
+
+        for (i, (path, source)) in audiofile_paths.iter().zip(sources).enumerate() {
+            if i > 0 && !self.file_gap.is_zero() {
+                self.append(Queued::Gap, audio::lasting(Box::new(Zero::<f32>::new(source.channels(), source.sample_rate())),
+                    self.file_gap));
+            }
+            info!("Queued audio file {}", path);
+            self.append(Queued::File(path.clone()), source);
+        }
+        self.append_roger_beep();
+        self.sink.pause();
+
+        Ok(())
+    }
+
+    // The same as queue_many(), taking the paths by value
+    pub fn queue_playlist(self: &Player, audiofile_paths: Vec<String>) -> Result<()> {
+        self.queue_many(&audiofile_paths)
+    }
+
+    // How many of the files (and streams and messages) queued are still to be played, counting
+    // the one that's playing
+    pub fn queue_len(self: &Player) -> usize {
+        self.lock_queued().entries.iter().filter(|entry| entry.queued.is_item()).count()
+    }
+
+    // The file (or stream or message) that's playing, or that's up next if we're paused or idle.
+    // None when there's nothing left.
+    pub fn current_item(self: &Player) -> Option<QueueItem> {
+        self.lock_queued().entries.iter().find(|entry| entry.queued.is_item()).map(QueueEntry::item)
+    }
+
+    // What's queued after current_item(), in the order it'll be played
+    pub fn remaining(self: &Player) -> Vec<QueueItem> {
+        self.lock_queued().entries.iter()
+            .filter(|entry| entry.queued.is_item())
+            .skip(1)
+            .map(QueueEntry::item)
+            .collect()
+    }
+
+    // Queues a file to be sent 'times' times with 'gap' of silence in between, e.g. for a net
+    // announcement. The file is only decoded once.
+    pub fn queue_audio_repeated(self: &Player, audiofile_path: String, times: u32, gap: Duration, repeats: Repeats) -> Result<()> {
+        // Which would otherwise queue up nothing but a roger beep
+        if times == 0 {
+            return Err(PlayerError::NoRepeats);
+        }
+        let source = self.load_file(&audiofile_path)?.buffered();
+
+        info!("Queued audio file {} {} times", audiofile_path, times);
+        for i in 0..times {
+            if i > 0 {
+                if repeats == Repeats::UnkeyBetween {
+                    // Each over gets its own roger beep, and the transmission is told when the
+                    // silence starts so that it can un-key for it
+                    self.append_roger_beep();
+                    let next_gap = Arc::clone(&self.next_gap);
+                    self.append(Queued::Gap, EmptyCallback::<f32>::new(Box::new(move || {
+                        *next_gap.lock().unwrap_or_else(PoisonError::into_inner) = Some((Instant::now(), gap));
+                    })));
+                }
+                self.append(Queued::Gap, audio::lasting(Box::new(Zero::<f32>::new(source.channels(), source.sample_rate())), gap));
+            }
+            self.append(Queued::File(audiofile_path.clone()), source.clone());
+        }
+        self.append_roger_beep();
+        self.sink.pause();
+
+        Ok(())
+    }
+
+    // Queues 'text' as Morse code, e.g. for identifying the station at the end of a transmission.
+    // See morse::cw_source() for what can be sent.
+    pub fn queue_cw(self: &Player, text: &str, wpm: u32, tone_hz: f32) -> Result<()> {
+        let source = morse::cw_source(text, wpm, tone_hz).map_err(PlayerError::Cw)?;
+
+        info!("Queued '{}' in Morse code", text);
+        self.append(Queued::Cw(text.to_string()), source);
+        self.append_roger_beep();
+        self.sink.pause();
+
+        Ok(())
+    }
+
+    // Queues 'digits' as DTMF tones, e.g. to bring up a link ahead of an announcement. Each digit
+    // is sent for 'tone' with 'gap' of silence after it; see dtmf::dtmf_source() for what can be
+    // sent. Nothing is queued if any of the digits can't be.
+    pub fn queue_dtmf(self: &Player, digits: &str, tone: Duration, gap: Duration) -> Result<()> {
+        let source = dtmf::dtmf_source(digits, tone, gap).map_err(PlayerError::Dtmf)?;
+
+        info!("Queued '{}' in DTMF", digits);
+        self.append(Queued::Dtmf(digits.to_string()), source);
+        self.append_roger_beep();
+        self.sink.pause();
+
+        Ok(())
+    }
+
+    // Transmits a steady tone for 'duration', e.g. for setting the radio's deviation, and returns
+    // once the radio has been un-keyed again. 'amplitude' is a fraction of full scale (before the
+    // volume is applied), so that the tone can be sent at a known reference level. Anything that
+    // was already queued goes out ahead of the tone.
+    pub fn transmit_tone(self: &Player, frequency_hz: f32, duration: Duration, amplitude: f32) -> Result<()> {
+        check_tone(frequency_hz, amplitude)?;
+
+        info!("Sending a {}Hz tone for {:?}", frequency_hz, duration);
+        self.append(Queued::Tone(frequency_hz), audio::lasting(Box::new(SineWave::new(frequency_hz).amplify(amplitude)), duration));
+        self.transmit_queued()
+    }
+
+    // Transmits two tones at once for 'duration', the usual test signal for an SSB transmitter's
+    // intermodulation distortion (e.g. 700Hz and 1900Hz). 'amplitudes' are those of the two tones,
+    // which are normally equal; between them they mustn't add up to more than full scale, or the
+    // peaks would clip and there'd be IMD before the audio ever got to the radio.
+    pub fn transmit_two_tone(self: &Player, frequencies_hz: (f32, f32), duration: Duration, amplitudes: (f32, f32)) -> Result<()> {
+        check_tone(frequencies_hz.0, amplitudes.0)?;
+        check_tone(frequencies_hz.1, amplitudes.1)?;
+        check_tone(frequencies_hz.0, amplitudes.0 + amplitudes.1)?;
+
+        info!("Sending a {}Hz + {}Hz two-tone test signal for {:?}", frequencies_hz.0, frequencies_hz.1, duration);
+        let tones = SineWave::new(frequencies_hz.0).amplify(amplitudes.0)
+            .mix(SineWave::new(frequencies_hz.1).amplify(amplitudes.1));
+        self.append(Queued::TwoTone(frequencies_hz.0, frequencies_hz.1), audio::lasting(Box::new(tones), duration));
+        self.transmit_queued()
+    }
+
+    // Keys the radio for 'duration' with nothing but silence going out, e.g. to tune an antenna
+    // or amplifier on a bare carrier, and returns once it's been un-keyed again. Leaving a carrier
+    // up for long is what cooks transmitters, so this refuses to go past MAX_TUNE_DURATION, or the
+    // max_transmit limit if that's shorter. (The CTCSS tone still goes along, if there is one.)
+    pub fn tune(self: &Player, duration: Duration) -> Result<()> {
+        self.check_tune_duration(duration)?;
+        // play() checks these too, but by then the carrier would already be queued behind
+        // whatever's on the air
+        if matches!(self.state()?, PlayerState::Keyed | PlayerState::Transmitting) {
+            return Err(PlayerError::AlreadyTransmitting);
+        }
+        if self.require_clear_channel && self.channel_is_busy()? {
+            return Err(PlayerError::ChannelBusy);
+        }
+
+        info!("Sending a carrier for {:?}", duration);
+        self.append(Queued::Carrier, audio::lasting(Box::new(Zero::<f32>::new(1, 48000)), duration));
+        self.transmit_queued()
+    }
+
+    // Keys the radio for 'duration' with no audio going to it at all (not even the CTCSS tone),
+    // e.g. for an SWR check, and returns once it's been un-keyed again. This keys PTT directly
+    // rather than going through the sink, so it refuses while there's anything queued (which
+    // would otherwise go out with it) as well as while a transmission is in progress. The same
+    // limits as tune() apply, and the duty cycle limit as for play(). Like any transmission,
+    // stop() and pause() cut it short.
+    pub fn test_key(self: &Player, duration: Duration) -> Result<()> {
+        self.check_tune_duration(duration)?;
+        let phase = {
+            let _transition = self.lock_transition();
+            let mut transmission = self.lock_transmission();
+            if transmission.as_ref().is_some_and(|transmission| transmission.phase().is_some()) || self.ptt_is_enabled()? {
+                return Err(PlayerError::AlreadyTransmitting);
+            }
+            if !self.sink.is_empty() {
+                return Err(PlayerError::AudioQueued);
+            }
+            if self.timed_out() {
+                return Err(PlayerError::TimedOut);
+            }
+            self.check_duty_cycle_for(duration)?;
+            if self.require_clear_channel && self.channel_is_busy()? {
+                return Err(PlayerError::ChannelBusy);
+            }
+            if let Some(previous) = transmission.take() {
+                previous.finish();
+            }
+
+            info!("Test keying for {:?}", duration);
+            self.set_ptt(true)?;
+            self.events.send(PlayerEvent::Keyed);
+            let test_key = self.start_transmission(Some(duration));
+            let phase = Arc::clone(&test_key.phase);
+            *transmission = Some(test_key);
+            phase
+        };
+
+        // Looked in on without holding the lock, so that stop() and pause() can get at it
+        loop {
+            let transition = self.lock_transition();
+            let mut transmission = self.lock_transmission();
+            match transmission.take_if(|transmission| Arc::ptr_eq(&transmission.phase, &phase) && transmission.phase().is_none()) {
+                Some(finished) => {
+                    finished.finish();
+                    // The thread gave up on releasing PTT, so the error's for us to return
+                    if self.ptt_is_enabled()? {
+                        self.set_ptt(false)?;
+                    }
+                    return Ok(());
+                }
+                // Stopped, and PTT released by stop()
+                None if transmission.as_ref().is_none_or(|transmission| !Arc::ptr_eq(&transmission.phase, &phase)) => {
+                    return Ok(());
+                }
+                None => (),
+            }
+            drop(transmission);
+            drop(transition);
+            thread::sleep(TRANSMISSION_POLL_INTERVAL);
+        }
+    }
+
+    // The watchdog counts from keying, so the lead delay has to fit under max_transmit too
+    fn check_tune_duration(self: &Player, duration: Duration) -> Result<()> {
+        if duration > MAX_TUNE_DURATION {
+            return Err(PlayerError::TuneTooLong{requested: duration, limit: MAX_TUNE_DURATION});
+        }
+        match self.max_transmit {
+            Some(limit) if self.lead_delay + duration >= limit => {
+                Err(PlayerError::TuneTooLong{requested: duration, limit: limit.saturating_sub(self.lead_delay)})
+            },
+            _ => Ok(()),
+        }
+    }
+
+    // Sends everything that's queued as a single transmission, returning once it's over
+    fn transmit_queued(self: &Player) -> Result<()> {
+        self.sink.pause();
+        self.play()?;
+        self.wait_until_empty()
+    }
+
+    // Transmits 'text' in Morse code on its own, e.g. a beacon identifying itself between
+    // transmissions, and returns once the radio has been un-keyed again. The tone is the usual CW
+    // sidetone pitch; use queue_cw() for a different one.
+    pub fn transmit_cw(self: &Player, text: &str, wpm: u32) -> Result<()> {
+        self.queue_cw(text, wpm, CW_TONE_HZ)?;
+        self.transmit_queued()
+    }
+
+    // Transmits 'digits' as DTMF tones, e.g. to control a repeater or link, and returns once the
+    // radio has been un-keyed again. See queue_dtmf().
+    pub fn transmit_dtmf(self: &Player, digits: &str, tone: Duration, gap: Duration) -> Result<()> {
+        self.queue_dtmf(digits, tone, gap)?;
+        self.transmit_queued()
+    }
+
+    fn append_roger_beep(self: &Player) {
+        if let Some(beep) = self.roger_beep {
+            self.append(Queued::RogerBeep, beep.source());
+        }
+    }
+
+    // Cuts the audio off and sends the roger beep on the courtesy sink instead, for a transmission
+    // that's being paused. The transmission waits for it before the tail delay.
+    fn send_roger_beep_now(self: &Player) {
+        if let (Some(beep), Some(courtesy_sink)) = (self.roger_beep, &self.courtesy_sink) {
+            self.sink.pause();
+            courtesy_sink.set_volume(self.sink.volume());
+            courtesy_sink.append(self.with_ctcss(beep.source()));
+            courtesy_sink.play();
+        }
+    }
+
+    // Everything queued goes through here, so that we can tell what's playing (and so that all of
+    // it makes room for the CTCSS tone)
+    fn append<S: Source<Item = f32> + Send + 'static>(self: &Player, queued: Queued, source: S) {
+        self.append_labelled(queued, None, source);
+    }
+
+    fn append_labelled<S: Source<Item = f32> + Send + 'static>(self: &Player, queued: Queued, label: Option<String>, source: S) {
+        if let (Queued::File(_) | Queued::Stream, Some(auto_id)) = (&queued, &self.auto_id) {
+            self.append(Queued::AutoId, IdSlot{auto_id: Arc::clone(auto_id), id: None});
+        }
+
+        let duration = match (&queued, &self.auto_id) {
+            (Queued::AutoId, Some(auto_id)) => Some(auto_id.expected_duration()),
+            _ => source.total_duration(),
+        };
+
+        let mut queued_log = self.lock_queued();
+        // Anything queued once the last of the audio is done is the start of something new, as
+        // far as position() is concerned
+        if queued_log.entries.is_empty() {
+            queued_log.finished = Some(Duration::ZERO);
+            self.lock_played().reset();
+        }
+        // The roger beep marks the end of the over, so one that's now got more audio after it
+        // isn't sent after all. A gap (between repeats) is the end of an over too.
+        if !matches!(queued, Queued::Gap) {
+            if let Some(beep) = queued_log.entries.back().filter(|entry| matches!(entry.queued, Queued::RogerBeep)) {
+                beep.dropped.store(true, Ordering::SeqCst);
+            }
+        }
+        // There's nothing to say about silence
+        let aired = match (&queued, &self.ptt.log) {
+            (Queued::Gap, _) | (_, None) => None,
+            (_, Some(log)) => Some((queued.describe(), log.lock().unwrap_or_else(PoisonError::into_inner).aired())),
+        };
+        let dropped = Arc::new(AtomicBool::new(false));
+        queued_log.entries.push_back(QueueEntry{queued, label, dropped: Arc::clone(&dropped), duration});
+
+        let source = self.with_ctcss(Box::new(source));
+        self.sink.append(Box::new(Droppable{source, dropped, started: false, aired}));
+    }
+
+    // The tone itself goes out on the tone sink, see Ctcss, so there's only room to be made for it
+    fn with_ctcss(self: &Player, source: AudioSource) -> AudioSource {
+        match (self.ctcss_hz, &self.tone_sink) {
+            (Some(_), Some(_)) => Box::new(source.amplify(1.0 - CTCSS_LEVEL)),
+            (Some(frequency_hz), None) => audio::with_tone(source, frequency_hz, CTCSS_LEVEL, Arc::clone(&self.ctcss_phase)),
+            (None, _) => source,
+        }
+    }
+
+    fn lock_queued(self: &Player) -> MutexGuard<'_, QueueLog> {
+        let mut queued = self.queued.lock().unwrap_or_else(PoisonError::into_inner);
+        // Forget about whatever the sink is done with
+        let remaining = self.sink.len();
+        while queued.entries.len() > remaining {
+            if let Some(entry) = queued.entries.pop_front().filter(|entry| !entry.dropped.load(Ordering::SeqCst)) {
+                queued.finished = queued.finished.zip(entry.duration).map(|(finished, duration)| finished + duration);
+            }
+        }
+        queued
+    }
+
+    fn lock_played(self: &Player) -> MutexGuard<'_, Stopwatch> {
+        self.played.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // What's playing (or would be, if we weren't paused), in the same terms as SeekUnsupported
+    fn playing(self: &Player) -> String {
+        self.lock_queued().entries.front().map_or_else(|| "nothing".to_string(), |entry| entry.queued.describe())
+    }
+
+    // Drops everything that's yet to be played. Unlike stop(), this leaves a transmission in
+    // progress alone: whatever's playing right now plays to the end, and then the transmission
+    // ends as usual since there's nothing left. When nothing's playing, everything goes.
+    pub fn clear_queue(self: &Player) {
+        let mut queued = self.lock_queued();
+        if self.sink.is_paused() {
+            self.sink.clear();
+            queued.entries.clear();
+            return;
+        }
+
+        if !queued.entries.is_empty() {
+            for entry in queued.entries.iter().skip(1) {
+                entry.dropped.store(true, Ordering::SeqCst);
+            }
+            // The sink still counts the dropped sources until it gets to them, which it does as soon
+            // as the current one ends
+            queued.entries.truncate(1);
+        }
+    }
+
+    // How long the audio that's queued has been playing for, counting from the first of it since
+    // the queue was last empty and leaving out the lead delay and any time spent paused. None when
+    // there's nothing queued.
+    pub fn position(self: &Player) -> Option<Duration> {
+        match self.lock_queued().entries.is_empty() {
+            true => None,
+            false => Some(self.lock_played().read()),
+        }
+    }
+
+    // position() as a fraction of the length of everything queued (0.0 to 1.0), e.g. for a
+    // progress bar. None when there's nothing queued, or something queued doesn't know how long it
+    // is (like a stream, or a file in a format that doesn't say).
+    pub fn progress(self: &Player) -> Option<f32> {
+        let total = self.lock_queued().total_duration().filter(|total| !total.is_zero())?;
+        let position = self.position()?;
+        Some((position.as_secs_f32() / total.as_secs_f32()).min(1.0))
+    }
+
+    // Jumps to 'pos' in whatever's playing, e.g. to skip ahead in a long bulletin or resume it part
+    // of the way in. The radio stays keyed throughout, so this can be done mid-transmission.
+    //
+    // Not everything can be seeked in, in which case this fails with SeekUnsupported: of rodio's
+    // decoders, WAV and MP3 can, but FLAC and Ogg Vorbis can't. The generated audio (tones, Morse
+    // code, DTMF, ...) can, but queue_audio_repeated() can't, since its repeats share one buffer.
+    pub fn seek(self: &Player, pos: Duration) -> Result<()> {
+        let from = self.sink.get_pos();
+        self.sink.try_seek(pos).map_err(|e| match e {
+            SeekError::NotSupported{underlying_source} => PlayerError::SeekUnsupported{
+                playing: self.playing(),
+                source_type: underlying_source,
+            },
+            e => PlayerError::Seek{playing: self.playing(), message: e.to_string()},
+        })?;
+        self.lock_played().skip(from, pos);
+        Ok(())
+    }
+
+    // Decodes a file and applies whatever processing is turned on
+    fn load_file(self: &Player, audiofile_path: &str) -> Result<AudioSource> {
+        self.process(Box::new(Player::decode_file(audiofile_path)?.convert_samples()), &format!("'{}'", audiofile_path))
+    }
+
+    // 'what' says what 'source' is for errors, in the same terms as Queued::describe()
+    fn process(self: &Player, source: AudioSource, what: &str) -> Result<AudioSource> {
+        self.check_format(&source, what)?;
+
+        let source = match self.downmix {
+            true => audio::downmix(source),
+            false => source,
+        };
+        let source = match self.output_format {
+            Some(output) => audio::resample(source, output.sample_rate),
+            None => source,
+        };
+        let source = audio::band_limit(source, self.passband);
+        // Normalising last means the level is that of what actually goes out, other than the fades
+        // and whatever peaks the limiter catches
+        let source = match self.normalization {
+            Some(normalization) => audio::normalize(source, normalization),
+            None => source,
+        };
+        let source = match self.limiter {
+            Some(threshold) => audio::limit(source, threshold),
+            None => source,
+        };
+        let (fade_in, fade_out) = self.fades();
+        Ok(audio::fade(source, fade_in, fade_out))
+    }
+
+    // Anything can be converted to the device's format, except audio that claims to have no
+    // channels or no samples per second (rodio panics in the audio thread), so that's caught here
+    // instead
+    fn check_format(self: &Player, source: &AudioSource, what: &str) -> Result<()> {
+        let (channels, sample_rate) = (source.channels(), source.sample_rate());
+        if channels == 0 || sample_rate == 0 {
+            return Err(PlayerError::UnsupportedFormat{playing: what.to_string(), channels, sample_rate});
+        }
+
+        if let Some(output) = self.output_format {
+            if sample_rate != output.sample_rate {
+                debug!("Resampling {} from {}Hz to the audio device's {}Hz", what, sample_rate, output.sample_rate);
+            }
+            if channels > output.channels && !self.downmix {
+                warn!("{} has {} channels, but the audio device only has {}", what, channels, output.channels);
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_file(audiofile_path: &str) -> Result<Decoder<BufReader<File>>> {
+        let file = BufReader::new(File::open(audiofile_path)
+            .map_err(|source| PlayerError::FileOpen{path: audiofile_path.to_string(), source})?);
+        Decoder::new(file)
+            .map_err(|source| PlayerError::Decode{path: audiofile_path.to_string(), source})
+    }
+
+    // How long 'audiofile_path' will take to play (before any fades, gaps or roger beep), e.g. to
+    // check it'll fit in the max transmit time before keying. Formats whose headers don't say are
+    // decoded all the way through to find out, and None means the file doesn't make sense as
+    // audio at all (no channels or no sample rate).
+    pub fn audio_duration(audiofile_path: &str) -> Result<Option<Duration>> {
+        let source = Player::decode_file(audiofile_path)?;
+        if let Some(duration) = source.total_duration() {
+            return Ok(Some(duration));
+        }
+
+        let samples_per_sec = source.channels() as u64 * source.sample_rate() as u64;
+        if samples_per_sec == 0 {
+            return Ok(None);
+        }
+        let samples = source.count() as u64;
+        Ok(Some(Duration::from_secs(samples / samples_per_sec)
+            + Duration::from_nanos(samples % samples_per_sec * 1_000_000_000 / samples_per_sec)))
+    }
+
+    // Keys the radio and returns, leaving the queued audio to start once the lead delay is up.
+    // PTT is released automatically (after the tail delay) once all of the queued audio has been
+    // played. If the previous transmission is still waiting out its tail, this waits for it.
+    pub fn play(self: &Player) -> Result<()> {
+        let _transition = self.lock_transition();
+
+        // Reap the previous transmission, unless it's still going
+        let mut transmission = self.lock_transmission();
+        if transmission.as_ref().is_some_and(Transmission::resume) {
+            info!("Carrying on the transmission within the hang time");
+            return Ok(());
+        }
+        if let Some(Phase::Keying | Phase::Transmitting | Phase::Gap) = transmission.as_ref().and_then(Transmission::phase) {
+            return Err(PlayerError::AlreadyTransmitting);
+        }
+        if let Some(previous) = transmission.take() {
+            previous.finish();
+        }
+
+        if self.timed_out() {
+            return Err(PlayerError::TimedOut);
+        }
+        self.check_duty_cycle()?;
+        if self.ptt_is_enabled()? || !self.sink.is_paused() {
+            return Err(PlayerError::AlreadyTransmitting);
+        }
+        if self.require_clear_channel && self.channel_is_busy()? {
+            return Err(PlayerError::ChannelBusy);
+        }
+
+        info!("Starting a transmission");
+        self.set_ptt(true)?;
+        self.events.send(PlayerEvent::Keyed);
+        *transmission = Some(self.start_transmission(None));
+
+        Ok(())
+    }
+
+    // Waits for the channel to have been clear (see channel_is_busy()) for the clear hold time,
+    // then does the same as play(). If the channel doesn't clear up within 'timeout', this gives
+    // up with ChannelBusyTimeout and nothing is transmitted.
+    pub fn play_when_clear(self: &Player, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut clear_since:Option<Instant> = None;
+        loop {
+            let now = Instant::now();
+            if self.channel_is_busy()? {
+                clear_since = None;
+            } else if now - *clear_since.get_or_insert(now) >= self.clear_hold {
+                match self.play() {
+                    // Someone got in between our last look and play()'s
+                    Err(PlayerError::ChannelBusy) => clear_since = None,
+                    result => return result,
+                }
+            }
+
+            if now >= deadline {
+                return Err(PlayerError::ChannelBusyTimeout{timeout});
+            }
+            thread::sleep(CHANNEL_POLL_INTERVAL.min(deadline - now));
+        }
+    }
+
+    // Blocks until the transmission is over and the radio has been un-keyed, so that a
+    // transmission is just play() followed by wait_until_empty(). If it ended with audio still
+    // queued, that's an error: TimedOut if it ran into the transmit time limit, and AlreadyPaused
+    // if it was paused (or never started, if there was no play() first).
+    pub fn wait_until_empty(self: &Player) -> Result<()> {
+        // Looked in on rather than joined, so that play() can still carry the transmission on
+        // within the hang time
+        let _transition = loop {
+            let transition = self.lock_transition();
+            let mut transmission = self.lock_transmission();
+            match transmission.take_if(|transmission| transmission.phase().is_none()) {
+                Some(finished) => {
+                    finished.finish();
+                    break transition;
+                }
+                None if transmission.is_none() => break transition,
+                None => (),
+            }
+            drop(transmission);
+            drop(transition);
+            thread::sleep(TRANSMISSION_POLL_INTERVAL);
+        };
+
+        if !self.sink.is_empty() {
+            return Err(if self.timed_out() { PlayerError::TimedOut } else { PlayerError::AlreadyPaused });
+        }
+        // The transmission's gone, so if the radio is still keyed (say by set_ptt()) we're the
+        // only one driving the line
+        if self.ptt_is_enabled()? {
+            self.events.send(PlayerEvent::QueueDrained);
+            self.sink.pause();
+            thread::sleep(self.tail_delay);
+            self.set_ptt(false)?;
+            self.events.send(PlayerEvent::TransmitEnded);
+        }
+
+        Ok(())
+    }
+
+    // Whether the transmission thread is done with the radio (or there isn't one), for waiting
+    // on it without blocking
+    #[cfg(feature = "async")]
+    pub(crate) fn transmission_is_over(self: &Player) -> bool {
+        self.lock_transmission().as_ref().is_none_or(|transmission| transmission.phase().is_none())
+    }
+
+    // Plays what's queued, or for a test key holds the radio keyed for that long instead
+    fn start_transmission(self: &Player, test_key: Option<Duration>) -> Transmission {
+        // A gap that started as the last transmission was stopped is long gone
+        *self.next_gap.lock().unwrap_or_else(PoisonError::into_inner) = None;
+
+        let (release, released) = mpsc::channel::<()>();
+        let phase = Arc::new(Mutex::new(Phase::Keying));
+        let transmission = TransmissionThread{
+            released,
+            phase: Arc::clone(&phase),
+            sink: Arc::clone(&self.sink),
+            courtesy_sink: self.courtesy_sink.clone(),
+            ctcss: self.ctcss_hz.zip(self.tone_sink.clone()).filter(|_| test_key.is_none()).map(|(frequency_hz, sink)| Ctcss{
+                sink,
+                frequency_hz,
+                sample_rate: self.output_format.unwrap_or_default().sample_rate,
+                phase: Arc::clone(&self.ctcss_phase),
+            }),
+            ptt: Arc::clone(&self.ptt),
+            timed_out: Arc::clone(&self.timed_out),
+            next_gap: Arc::clone(&self.next_gap),
+            played: Arc::clone(&self.played),
+            lead_delay: self.lead_delay,
+            tail_delay: self.tail_delay,
+            hang_time: self.hang_time,
+            max_transmit: self.max_transmit,
+            events: self.events.clone(),
+        };
+
+        let thread = match test_key {
+            Some(duration) => thread::spawn(move || transmission.hold(duration)),
+            None => thread::spawn(move || transmission.run()),
+        };
+        Transmission{release, phase, thread}
+    }
+
+    fn lock_transition(self: &Player) -> MutexGuard<'_, ()> {
+        self.transition.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_transmission(self: &Player) -> MutexGuard<'_, Option<Transmission>> {
+        self.transmission.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Makes sure the transmission thread isn't racing us for the PTT line
+    fn cancel_transmission(self: &Player) {
+        let transmission = self.lock_transmission().take();
+        if let Some(transmission) = transmission {
+            transmission.cancel();
+        }
+    }
+
+    // Ends the transmission in progress: the audio is paused (and picks up from there with the
+    // next play()), and the radio is un-keyed once the tail delay is up. Like play(), this returns
+    // without waiting for that to happen.
+    pub fn pause(self: &Player) -> Result<()> {
+        let _transition = self.lock_transition();
+
+        let transmission = self.lock_transmission();
+        match transmission.as_ref().filter(|t| matches!(t.phase(), Some(Phase::Keying | Phase::Transmitting | Phase::Gap | Phase::Hanging))) {
+            Some(transmission) => {
+                info!("Pausing the transmission");
+                if transmission.phase() == Some(Phase::Transmitting) {
+                    self.send_roger_beep_now();
+                }
+                transmission.release();
+                Ok(())
+            }
+            None => Err(PlayerError::AlreadyPaused),
+        }
+    }
+
+    // Aborts whatever is going on and returns the player to the same state as right after
+    // construction: nothing queued, the radio un-keyed and no pending transmit timeout. The audio
+    // is cut off straight away, but if the radio was keyed it's still only un-keyed once the
+    // tail delay is up, so that it doesn't drop out in the middle of the last syllable. This
+    // returns once it has been.
+    pub fn stop(self: &Player) -> Result<()> {
+        let _transition = self.lock_transition();
+
+        info!("Stopping");
+        self.cancel_transmission();
+        let was_keyed = self.ptt_is_enabled()?;
+        self.sink.clear();
+        if let Some(courtesy_sink) = &self.courtesy_sink {
+            courtesy_sink.clear();
+        }
+        self.lock_queued().entries.clear();
+        *self.lock_played() = Stopwatch::default();
+        if was_keyed {
+            thread::sleep(self.tail_delay);
+        }
+        self.set_ptt(false)?;
+        self.clear_timeout();
+        if was_keyed {
+            self.events.send(PlayerEvent::TransmitEnded);
+        }
+
+        Ok(())
+    }
+
+    // Releases PTT and closes the PTT device. Dropping the Player does the same thing, but this
+    // gives the caller a chance to find out if it failed.
+    pub fn close(mut self) -> Result<()> {
+        self.release()
+    }
+
+    // Moves the Player onto a thread of its own, so that none of its calls (decoding files,
+    // waiting out a previous transmission's tail, ...) hold up the caller. See PlayerHandle.
+    pub fn spawn(self) -> PlayerHandle {
+        let (commands, received) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            for command in received {
+                let result = match command {
+                    Command::Queue(path) => self.queue_audio(path),
+                    Command::Play => self.play(),
+                    Command::Pause => self.pause(),
+                    Command::Stop => self.stop(),
+                    Command::Shutdown => break,
+                };
+                // Nobody is waiting on the result, so report it the only ways we can
+                if let Err(e) = result {
+                    error!("{:#}", e);
+                    self.events.send(PlayerEvent::Error(format!("{:#}", e)));
+                }
+            }
+
+            self.close()
+        });
+
+        PlayerHandle{commands, thread: Some(thread)}
+    }
+
+    fn release(self: &mut Player) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+
+        // Neither the transmission thread nor the signal handler should touch the PTT device once
+        // it's been closed
+        self.cancel_transmission();
+        let mut signal_ptt = SIGNAL_PTT.lock().unwrap_or_else(PoisonError::into_inner);
+        if signal_ptt.as_ref().is_some_and(|ptt| ptt.as_ptr() == Arc::as_ptr(&self.ptt)) {
+            *signal_ptt = None;
+        }
+        drop(signal_ptt);
+
+        // Don't leave the radio keyed just because we went away mid-transmission
+        self.sink.pause();
+        let released = self.ptt.set_keyed(false);
+
+        // Close the device even if releasing PTT failed, so that it isn't leaked
+        self.ptt.control().close().map_err(PlayerError::Ptt)?;
+
+        released
+    }
+
+    // Lets a front-end (or anything driving play() and pause()) find out which of them currently
+    // makes sense, rather than finding out from the error
+    pub fn state(self: &Player) -> Result<PlayerState> {
+        let phase = self.lock_transmission().as_ref().and_then(Transmission::phase);
+        if let Some(phase) = phase {
+            Ok(match phase {
+                Phase::Keying => PlayerState::Keyed,
+                Phase::Transmitting => PlayerState::Transmitting,
+                Phase::Gap => PlayerState::Gap,
+                Phase::Unkeying => PlayerState::Unkeying,
+                Phase::Hanging => PlayerState::Keyed,
+            })
+        } else if self.ptt_is_enabled()? {
+            Ok(if self.sink.is_paused() { PlayerState::Keyed } else { PlayerState::Transmitting })
+        } else if !self.sink.is_empty() {
+            Ok(PlayerState::Paused)
+        } else {
+            Ok(PlayerState::Idle)
+        }
+    }
+
+    // Reports the logical keyed state of the radio, accounting for inverted polarity
+    pub fn ptt_is_enabled(self: &Player) -> Result<bool> {
+        self.ptt.is_keyed()
+    }
+
+    // Forces the radio into the keyed (transmit) or un-keyed (receive) state. This is idempotent,
+    // unlike toggling, so two rapid calls can't leave the radio stuck keyed.
+    pub fn set_ptt(self: &Player, keyed: bool) -> Result<()> {
+        self.ptt.set_keyed(keyed)
+    }
+
+    pub fn toggle_ptt(self: &Player) -> Result<()> {
+        let _transition = self.lock_transition();
+
+        self.set_ptt(!self.ptt_is_enabled()?)
+    }
+}
+
+// Options for constructing a Player. Only the audio device and a way of keying the radio (either a
+// TTY or a PttControl backend) are required; everything else defaults to the same behaviour as
+// Player::for_devices().
+pub struct PlayerBuilder {
+    tty_path: Option<String>,
+    ptt_control: Option<Box<dyn PttControl>>,
+    audio_device: Option<AudioDevice>,
+    audio_sink: Option<Arc<dyn AudioSink>>,
+    courtesy_sink: Option<Arc<dyn AudioSink>>,
+    tone_sink: Option<Arc<dyn AudioSink>>,
+    transmission_log: Option<PathBuf>,
+    ptt_line: PttLine,
+    invert_ptt: bool,
+    lead_delay: Duration,
+    tail_delay: Duration,
+    hang_time: Duration,
+    max_transmit: Option<Duration>,
+    duty_cycle_limit: Option<DutyCycleLimit>,
+    require_clear_channel: bool,
+    clear_hold: Duration,
+    roger_beep: Option<RogerBeep>,
+    auto_id: Option<AutoIdConfig>,
+    passband: Passband,
+    downmix: Option<bool>,
+    normalization: Option<Normalization>,
+    limiter: Option<f32>,
+    ctcss_hz: Option<f32>,
+    #[cfg(feature = "tts")]
+    tts: crate::tts::TtsEngine,
+    file_gap: Duration,
+    fade_in: Option<Duration>,
+    fade_out: Option<Duration>,
+    events: Events,
+    volume: Option<f32>,
+    max_volume: f32,
+    signal_handler: bool,
+    dry_run: bool,
+}
+
+impl Default for PlayerBuilder {
+    fn default() -> PlayerBuilder {
+        PlayerBuilder{
+            tty_path: None,
+            ptt_control: None,
+            audio_device: None,
+            audio_sink: None,
+            courtesy_sink: None,
+            tone_sink: None,
+            transmission_log: None,
+            ptt_line: PttLine::default(),
+            invert_ptt: false,
+            lead_delay: DEFAULT_PTT_DELAY,
+            tail_delay: DEFAULT_PTT_DELAY,
+            hang_time: Duration::ZERO,
+            max_transmit: None,
+            duty_cycle_limit: None,
+            require_clear_channel: false,
+            clear_hold: DEFAULT_CLEAR_HOLD,
+            roger_beep: None,
+            auto_id: None,
+            passband: Passband::default(),
+            downmix: None,
+            normalization: None,
+            limiter: None,
+            ctcss_hz: None,
+            #[cfg(feature = "tts")]
+            tts: crate::tts::TtsEngine::default(),
+            file_gap: Duration::ZERO,
+            fade_in: None,
+            fade_out: None,
+            events: Events::default(),
+            volume: None,
+            max_volume: MAX_VOLUME,
+            signal_handler: false,
+            dry_run: false,
+        }
+    }
+}
+
+impl PlayerBuilder {
+    pub fn tty_path(mut self, tty_path: String) -> PlayerBuilder {
+        self.tty_path = Some(tty_path);
+        self
+    }
+
+    // Keys the radio with the given backend instead of a modem control line of 'tty_path'
+    pub fn ptt_control(mut self, control: Box<dyn PttControl>) -> PlayerBuilder {
+        self.ptt_control = Some(control);
+        self
+    }
+
+    pub fn audio_device(mut self, audio_device_name: String) -> PlayerBuilder {
+        self.audio_device = Some(AudioDevice::Name(audio_device_name));
+        self
+    }
+
+    // Opens whichever audio device 'pattern' matches the name of, see Player::for_devices_matching()
+    pub fn audio_device_matching(mut self, pattern: Regex) -> PlayerBuilder {
+        self.audio_device = Some(AudioDevice::Pattern(pattern));
+        self
+    }
+
+    // Plays into 'sink' instead of opening an audio device, e.g. to stream the audio somewhere else.
+    // Since the Player can't know what format that wants, audio is passed on as it's decoded.
+    pub fn audio_sink(mut self, sink: Arc<dyn AudioSink>) -> PlayerBuilder {
+        self.audio_sink = Some(sink);
+        self
+    }
+
+    // Where the roger beep goes when a transmission is paused, alongside an 'audio_sink'. Without
+    // one, paused transmissions end without a beep.
+    pub fn courtesy_sink(mut self, sink: Arc<dyn AudioSink>) -> PlayerBuilder {
+        self.courtesy_sink = Some(sink);
+        self
+    }
+
+    // Where the CTCSS tone goes, alongside an 'audio_sink', so that it can be sent for as long as
+    // the radio is keyed. Without one, it's mixed into the audio instead, see Player::set_ctcss().
+    pub fn tone_sink(mut self, sink: Arc<dyn AudioSink>) -> PlayerBuilder {
+        self.tone_sink = Some(sink);
+        self
+    }
+
+    // Appends a line to 'path' for every over, see TransmissionLog
+    pub fn transmission_log(mut self, path: PathBuf) -> PlayerBuilder {
+        self.transmission_log = Some(path);
+        self
+    }
+
+    pub fn ptt_line(mut self, ptt_line: PttLine) -> PlayerBuilder {
+        self.ptt_line = ptt_line;
+        self
+    }
+
+    pub fn invert_ptt(mut self, invert: bool) -> PlayerBuilder {
+        self.invert_ptt = invert;
+        self
+    }
+
+    pub fn lead_delay(mut self, delay: Duration) -> PlayerBuilder {
+        self.lead_delay = delay;
+        self
+    }
+
+    pub fn tail_delay(mut self, delay: Duration) -> PlayerBuilder {
+        self.tail_delay = delay;
+        self
+    }
+
+    pub fn hang_time(mut self, hang_time: Duration) -> PlayerBuilder {
+        self.hang_time = hang_time;
+        self
+    }
+
+    pub fn max_transmit(mut self, limit: Option<Duration>) -> PlayerBuilder {
+        self.max_transmit = limit;
+        self
+    }
+
+    pub fn duty_cycle_limit(mut self, limit: Option<DutyCycleLimit>) -> PlayerBuilder {
+        self.duty_cycle_limit = limit;
+        self
+    }
+
+    // See Player::set_dry_run(). With no tty (or PTT backend) given, a dry run keys nothing at all.
+    pub fn dry_run(mut self, dry_run: bool) -> PlayerBuilder {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn require_clear_channel(mut self, required: bool) -> PlayerBuilder {
+        self.require_clear_channel = required;
+        self
+    }
+
+    pub fn clear_hold(mut self, hold: Duration) -> PlayerBuilder {
+        self.clear_hold = hold;
+        self
+    }
+
+    pub fn roger_beep(mut self, beep: Option<RogerBeep>) -> PlayerBuilder {
+        self.roger_beep = beep;
+        self
+    }
+
+    pub fn auto_id(mut self, auto_id: Option<AutoIdConfig>) -> PlayerBuilder {
+        self.auto_id = auto_id;
+        self
+    }
+
+    pub fn passband(mut self, passband: Passband) -> PlayerBuilder {
+        self.passband = passband;
+        self
+    }
+
+    // Whether to downmix to mono, see Player::set_downmix(). Left as None, this is decided by
+    // whether the audio device has just the one channel.
+    pub fn downmix(mut self, downmix: Option<bool>) -> PlayerBuilder {
+        self.downmix = downmix;
+        self
+    }
+
+    pub fn normalization(mut self, normalization: Option<Normalization>) -> PlayerBuilder {
+        self.normalization = normalization;
+        self
+    }
+
+    pub fn limiter(mut self, threshold: Option<f32>) -> PlayerBuilder {
+        self.limiter = threshold;
+        self
+    }
+
+    pub fn ctcss(mut self, frequency_hz: Option<f32>) -> PlayerBuilder {
+        self.ctcss_hz = frequency_hz;
+        self
+    }
+
+    #[cfg(feature = "tts")]
+    pub fn tts_engine(mut self, engine: crate::tts::TtsEngine) -> PlayerBuilder {
+        self.tts = engine;
+        self
+    }
+
+    pub fn file_gap(mut self, gap: Duration) -> PlayerBuilder {
+        self.file_gap = gap;
+        self
+    }
+
+    pub fn fades(self, fade_in: Duration, fade_out: Duration) -> PlayerBuilder {
+        self.fade_in(fade_in).fade_out(fade_out)
+    }
+
+    pub fn fade_in(mut self, fade: Duration) -> PlayerBuilder {
+        self.fade_in = Some(fade);
+        self
+    }
+
+    pub fn fade_out(mut self, fade: Duration) -> PlayerBuilder {
+        self.fade_out = Some(fade);
+        self
+    }
+
+    // Reports PlayerEvents to 'sender' as transmissions start and end
+    pub fn events(mut self, sender: mpsc::Sender<PlayerEvent>) -> PlayerBuilder {
+        self.events = Events(Some(sender));
+        self
+    }
+
+    pub fn volume(mut self, level: f32) -> PlayerBuilder {
+        self.volume = Some(level);
+        self
+    }
+
+    pub fn max_volume(mut self, max_volume: f32) -> PlayerBuilder {
+        self.max_volume = max_volume;
+        self
+    }
+
+    pub fn signal_handler(mut self, enabled: bool) -> PlayerBuilder {
+        self.signal_handler = enabled;
+        self
+    }
+
+    pub fn build(self) -> Result<Player> {
+        // Before any devices are opened, since the callsign might not be something Morse can send
+        let auto_id = self.auto_id.map(AutoId::new).transpose()?.map(Arc::new);
+        let transmission_log = self.transmission_log
+            .map(|path| TransmissionLog::open(&path).map(Mutex::new))
+            .transpose()
+            .map_err(PlayerError::TransmissionLog)?;
+
+        // Set up audio output
+        let (output, (sink, courtesy_sink, tone_sink), output_format) = match self.audio_sink {
+            Some(sink) => (None, (sink, self.courtesy_sink, self.tone_sink), None),
+            None => {
+                let audio_device = self.audio_device.ok_or(PlayerError::NoAudioDevice)?;
+                let (output, (sink, courtesy_sink, tone_sink), format) = AudioOutput::open(audio_device)?;
+                debug!("Audio device opened with {} channels at {}Hz", format.channels, format.sample_rate);
+                let (courtesy_sink, tone_sink) = (courtesy_sink as Arc<dyn AudioSink>, tone_sink as Arc<dyn AudioSink>);
+                (Some(output), (sink as Arc<dyn AudioSink>, Some(courtesy_sink), Some(tone_sink)), Some(format))
+            }
+        };
+
+        // Set up PTT
+        let control = match self.ptt_control {
+            Some(control) => control,
+            None if self.dry_run && self.tty_path.is_none() => Box::new(ptt::NullPtt::new()),
+            None => {
+                let tty_path = self.tty_path.ok_or(PlayerError::NoTty)?;
+                let control = ptt::open_line(&tty_path, self.ptt_line).map_err(|e| ptt_open_error(&tty_path, e))?;
+                info!("Keying the radio with {:?} on {}", self.ptt_line, tty_path);
+                control
+            },
+        };
+        // Ensure that PTT is NOT asserted so we don't hold open the RF link on startup
+        let player = Player{
+            ptt: Arc::new(Ptt{control: Mutex::new(control), invert: AtomicBool::new(self.invert_ptt), log: transmission_log,
+                keyed_periods: Mutex::new(KeyedPeriods::default()), dry_run: AtomicBool::new(self.dry_run),
+                dry_keyed: AtomicBool::new(false)}),
+            lead_delay: self.lead_delay,
+            tail_delay: self.tail_delay,
+            hang_time: self.hang_time,
+            max_transmit: self.max_transmit,
+            duty_cycle_limit: self.duty_cycle_limit,
+            require_clear_channel: self.require_clear_channel,
+            clear_hold: self.clear_hold,
+            roger_beep: self.roger_beep,
+            auto_id,
+            events: self.events,
+            transmission: Mutex::new(None),
+            timed_out: Arc::new(AtomicBool::new(false)),
+            queued: Mutex::new(QueueLog::default()),
+            passband: self.passband,
+            downmix: self.downmix.unwrap_or(output_format.is_some_and(|format| format.channels == 1)),
+            output_format,
+            normalization: self.normalization,
+            limiter: self.limiter,
+            ctcss_hz: self.ctcss_hz,
+            #[cfg(feature = "tts")]
+            tts: self.tts,
+            ctcss_phase: Arc::new(Mutex::new(0.0)),
+            file_gap: self.file_gap,
+            fade_in: self.fade_in,
+            fade_out: self.fade_out,
+            max_volume: clamp_volume(self.max_volume, MAX_VOLUME),
+            next_gap: Arc::new(Mutex::new(None)),
+            played: Arc::new(Mutex::new(Stopwatch::default())),
+            transition: Mutex::new(()),
+            closed: false,
+            sink,
+            courtesy_sink,
+            tone_sink,
+            output,
+        };
+        player.set_ptt(false)?;
+        // A new sink starts out playing, and whatever was queued first would start going out
+        // before the radio had been keyed
+        player.sink.pause();
+
+        if let Some(level) = self.volume {
+            player.set_volume(level);
+        }
+        if self.signal_handler {
+            player.install_signal_handler()?;
+        }
+
+        Ok(player)
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        // Nobody is around to handle the error, and panicking here could turn an unwind into an
+        // abort, so just report it
+        if let Err(e) = self.release() {
+            error!("Failed to close player: {:#}", e);
+        }
+    }
+}
+
+enum Command {
+    Queue(String),
+    Play,
+    Pause,
+    Stop,
+    Shutdown,
+}
+
+// Controls a Player running on its own thread (see Player::spawn()). Commands are carried out in
+// order, in the background, so these only fail if that thread has gone away; anything that goes
+// wrong with the commands themselves is reported as a PlayerEvent::Error.
+pub struct PlayerHandle {
+    commands: mpsc::Sender<Command>,
+    thread: Option<thread::JoinHandle<Result<()>>>,
+}
+
+impl PlayerHandle {
+    pub fn queue(self: &PlayerHandle, audiofile_path: String) -> Result<()> {
+        self.send(Command::Queue(audiofile_path))
+    }
+
+    pub fn play(self: &PlayerHandle) -> Result<()> {
+        self.send(Command::Play)
+    }
+
+    pub fn pause(self: &PlayerHandle) -> Result<()> {
+        self.send(Command::Pause)
+    }
+
+    pub fn stop(self: &PlayerHandle) -> Result<()> {
+        self.send(Command::Stop)
+    }
+
+    // Waits for the commands already sent to be carried out, then releases PTT and closes the
+    // Player. Dropping the handle does the same thing, but this reports whether it worked.
+    pub fn shutdown(mut self) -> Result<()> {
+        self.join()
+    }
+
+    fn send(self: &PlayerHandle, command: Command) -> Result<()> {
+        self.commands.send(command).map_err(|_| PlayerError::PlayerExited)
+    }
+
+    fn join(self: &mut PlayerHandle) -> Result<()> {
+        let Some(thread) = self.thread.take() else {
+            return Ok(());
+        };
+        // If the thread has already exited there's nobody to tell, but it still needs joining
+        let _ = self.commands.send(Command::Shutdown);
+        thread.join().map_err(|_| PlayerError::PlayerExited)?
+    }
+}
+
+impl Drop for PlayerHandle {
+    fn drop(&mut self) {
+        if let Err(e) = self.join() {
+            error!("Failed to shut down player: {:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // The line as the radio sees it, shared with the test once the Ptt has taken the control
+    struct MockPtt(Arc<Mutex<bool>>);
+
+    impl PttControl for MockPtt {
+        fn set(&mut self, asserted: bool) -> anyhow::Result<()> {
+            *self.0.lock().unwrap() = asserted;
+            Ok(())
+        }
+
+        fn get(&mut self) -> anyhow::Result<bool> {
+            Ok(*self.0.lock().unwrap())
+        }
+    }
+
+    // Refuses to release the line the first 'refusals' times it's asked to
+    struct StuckPtt {
+        line: Arc<Mutex<bool>>,
+        refusals: u32,
+    }
+
+    impl PttControl for StuckPtt {
+        fn set(&mut self, asserted: bool) -> anyhow::Result<()> {
+            if !asserted && *self.line.lock().unwrap() && self.refusals > 0 {
+                self.refusals -= 1;
+                return Err(anyhow::anyhow!("stuck"));
+            }
+            *self.line.lock().unwrap() = asserted;
+            Ok(())
+        }
+
+        fn get(&mut self) -> anyhow::Result<bool> {
+            Ok(*self.line.lock().unwrap())
+        }
+    }
+
+    fn mock_ptt(invert: bool) -> (Ptt, Arc<Mutex<bool>>) {
+        let line = Arc::new(Mutex::new(false));
+        let ptt = Ptt{control: Mutex::new(Box::new(MockPtt(Arc::clone(&line)))), invert: AtomicBool::new(invert), log: None,
+                       keyed_periods: Mutex::new(KeyedPeriods::default()), dry_run: AtomicBool::new(false),
+                       dry_keyed: AtomicBool::new(false)};
+        (ptt, line)
+    }
+
+    // Plays everything the moment it's allowed to, unless it's holding on to the audio, in which
+    // case nothing finishes until it's let go
+    pub(crate) struct MockSink {
+        state: Mutex<MockSinkState>,
+        drained: std::sync::Condvar,
+    }
+
+    struct MockSinkState {
+        queue: Vec<AudioSource>,
+        paused: bool,
+        holding: bool,
+        // See MockSink::real_time()
+        real_time: bool,
+        volume: f32,
+        // How many samples have been played
+        played: usize,
+        // The loudest of them, when playing in real time
+        peak: f32,
+    }
+
+    impl MockSink {
+        pub(crate) fn new(holding: bool) -> Arc<MockSink> {
+            Arc::new(MockSink{
+                state: Mutex::new(MockSinkState{queue: Vec::new(), paused: false, holding, real_time: false, volume: 1.0, played: 0, peak: 0.0}),
+                drained: std::sync::Condvar::new(),
+            })
+        }
+
+        // Plays the audio at its own pace, like a sound card, rather than all at once
+        fn real_time() -> Arc<MockSink> {
+            let sink = MockSink::new(false);
+            sink.state.lock().unwrap().real_time = true;
+            let playing = Arc::downgrade(&sink);
+            thread::spawn(move || while let Some(sink) = playing.upgrade() {
+                sink.advance(Duration::from_millis(10));
+                drop(sink);
+                thread::sleep(Duration::from_millis(10));
+            });
+            sink
+        }
+
+        fn advance(&self, by: Duration) {
+            let mut state = self.state.lock().unwrap();
+            let state = &mut *state;
+            let Some(source) = state.queue.first_mut().filter(|_| !state.paused) else {
+                return;
+            };
+            let samples = (source.sample_rate() as f64 * source.channels() as f64 * by.as_secs_f64()) as usize;
+            let mut peak = state.peak;
+            let played = source.by_ref().take(samples).inspect(|sample| peak = peak.max(sample.abs())).count();
+            state.played += played;
+            state.peak = peak;
+            if played < samples {
+                state.queue.remove(0);
+                self.drained.notify_all();
+            }
+        }
+
+        fn played(&self) -> usize {
+            self.state.lock().unwrap().played
+        }
+
+        fn peak(&self) -> f32 {
+            self.state.lock().unwrap().peak
+        }
+
+        pub(crate) fn let_go(&self) {
+            let mut state = self.state.lock().unwrap();
+            state.holding = false;
+            self.play_out(&mut state);
+        }
+
+        fn play_out(&self, state: &mut MockSinkState) {
+            if !state.paused && !state.holding && !state.real_time {
+                // Playing the sources through is what runs their callbacks
+                let played:usize = state.queue.drain(..).map(Iterator::count).sum();
+                state.played += played;
+                self.drained.notify_all();
+            }
+        }
+    }
+
+    impl AudioSink for MockSink {
+        fn append(&self, source: AudioSource) {
+            let mut state = self.state.lock().unwrap();
+            state.queue.push(source);
+            self.play_out(&mut state);
+        }
+
+        fn play(&self) {
+            let mut state = self.state.lock().unwrap();
+            state.paused = false;
+            self.play_out(&mut state);
+        }
+
+        fn pause(&self) {
+            self.state.lock().unwrap().paused = true;
+        }
+
+        fn is_paused(&self) -> bool {
+            self.state.lock().unwrap().paused
+        }
+
+        fn clear(&self) {
+            self.state.lock().unwrap().queue.clear();
+            self.drained.notify_all();
+        }
+
+        fn len(&self) -> usize {
+            self.state.lock().unwrap().queue.len()
+        }
+
+        fn sleep_until_end(&self) {
+            let state = self.state.lock().unwrap();
+            let _state = self.drained.wait_while(state, |state| !state.queue.is_empty()).unwrap();
+        }
+
+        fn volume(&self) -> f32 {
+            self.state.lock().unwrap().volume
+        }
+
+        fn set_volume(&self, volume: f32) {
+            self.state.lock().unwrap().volume = volume;
+        }
+
+        fn get_pos(&self) -> Duration {
+            Duration::ZERO
+        }
+
+        fn try_seek(&self, _pos: Duration) -> std::result::Result<(), SeekError> {
+            Err(SeekError::NotSupported{underlying_source: "MockSink"})
+        }
+    }
+
+    pub(crate) fn mock_builder(sink: &Arc<MockSink>) -> (PlayerBuilder, Arc<Mutex<bool>>) {
+        let line = Arc::new(Mutex::new(false));
+        let builder = Player::builder()
+            .ptt_control(Box::new(MockPtt(Arc::clone(&line))))
+            .audio_sink(Arc::clone(sink) as Arc<dyn AudioSink>)
+            .lead_delay(Duration::from_millis(10))
+            .tail_delay(Duration::from_millis(10));
+        (builder, line)
+    }
+
+    fn mock_player(sink: &Arc<MockSink>) -> (Player, Arc<Mutex<bool>>) {
+        let (builder, line) = mock_builder(sink);
+        (builder.build().unwrap(), line)
+    }
+
+    // A 16-bit mono WAV file of 'samples' samples of silence at 8kHz
+    pub(crate) fn wav(samples: u32) -> std::io::Cursor<Vec<u8>> {
+        let mut wav = Vec::new();
+        wav.extend(b"RIFF");
+        wav.extend((36 + samples * 2).to_le_bytes());
+        wav.extend(b"WAVEfmt ");
+        wav.extend(16u32.to_le_bytes());
+        // PCM, mono, 8kHz, 16000 bytes per second, 2 bytes per frame, 16 bits
+        for field in [1u16, 1] {
+            wav.extend(field.to_le_bytes());
+        }
+        wav.extend(8000u32.to_le_bytes());
+        wav.extend(16000u32.to_le_bytes());
+        for field in [2u16, 16] {
+            wav.extend(field.to_le_bytes());
+        }
+        wav.extend(b"data");
+        wav.extend((samples * 2).to_le_bytes());
+        wav.resize(wav.len() + samples as usize * 2, 0);
+        std::io::Cursor::new(wav)
+    }
+
+    fn roger_beep_samples() -> usize {
+        RogerBeep::default().source().count()
+    }
+
+    fn wait_for_state(player: &Player, state: PlayerState) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while player.state().unwrap() != state {
+            assert!(Instant::now() < deadline, "Player never got to {}", state);
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn ptt_drives_the_line_directly() {
+        let (ptt, line) = mock_ptt(false);
+
+        ptt.set_keyed(true).unwrap();
+        assert!(*line.lock().unwrap());
+        assert!(ptt.is_keyed().unwrap());
+
+        ptt.set_keyed(false).unwrap();
+        assert!(!*line.lock().unwrap());
+        assert!(!ptt.is_keyed().unwrap());
+    }
+
+    #[test]
+    fn inverted_ptt_keys_with_the_line_deasserted() {
+        let (ptt, line) = mock_ptt(true);
+
+        ptt.set_keyed(true).unwrap();
+        assert!(!*line.lock().unwrap());
+        assert!(ptt.is_keyed().unwrap());
+
+        ptt.set_keyed(false).unwrap();
+        assert!(*line.lock().unwrap());
+        assert!(!ptt.is_keyed().unwrap());
+    }
+
+    #[test]
+    fn null_ptt_remembers_its_line() {
+        let mut ptt = ptt::NullPtt::new();
+        assert!(!ptt.is_asserted().unwrap());
+        ptt.assert().unwrap();
+        assert!(ptt.is_asserted().unwrap());
+        ptt.deassert().unwrap();
+        assert!(!ptt.is_asserted().unwrap());
+    }
+
+    #[test]
+    fn a_transmission_keys_and_then_releases() {
+        let sink = MockSink::new(false);
+        let (builder, line) = mock_builder(&sink);
+        let (events, received) = mpsc::channel();
+        let player = builder.events(events).build().unwrap();
+
+        player.transmit_tone(1000.0, Duration::from_millis(50), 0.5).unwrap();
+        assert!(!*line.lock().unwrap());
+        assert_eq!(player.state().unwrap(), PlayerState::Idle);
+
+        let events:Vec<_> = received.try_iter().collect();
+        assert!(matches!(events.first(), Some(PlayerEvent::Keyed)));
+        assert!(matches!(events.last(), Some(PlayerEvent::TransmitEnded)));
+    }
+
+    #[test]
+    fn waiting_ends_with_the_transmission() {
+        let sink = MockSink::real_time();
+        let (builder, line) = mock_builder(&sink);
+        let player = Arc::new(builder.max_transmit(Some(Duration::from_millis(200))).build().unwrap());
+        let wait = |call: fn(&Player) -> Result<()>| {
+            let (done, finished) = mpsc::channel();
+            let player = Arc::clone(&player);
+            thread::spawn(move || done.send(call(&player)));
+            finished
+        };
+
+        // The time limit cuts the tone off with most of it still to go
+        let finished = wait(|player| player.transmit_tone(1000.0, Duration::from_secs(10), 0.5));
+        assert!(matches!(finished.recv_timeout(Duration::from_secs(4)).unwrap(), Err(PlayerError::TimedOut)));
+        assert!(!*line.lock().unwrap());
+
+        // And so does pausing
+        player.stop().unwrap();
+        player.queue_bytes(wav(80000).into_inner(), None).unwrap();
+        player.play().unwrap();
+        let finished = wait(|player| player.wait_until_empty());
+        wait_for_state(&player, PlayerState::Transmitting);
+        player.pause().unwrap();
+        assert!(matches!(finished.recv_timeout(Duration::from_secs(4)).unwrap(), Err(PlayerError::AlreadyPaused)));
+        assert!(!*line.lock().unwrap());
+
+        // Which leaves the rest to be played with the next play()
+        player.clear_queue();
+        player.queue_bytes(wav(800).into_inner(), None).unwrap();
+        player.play().unwrap();
+        player.wait_until_empty().unwrap();
+    }
+
+    #[test]
+    fn the_roger_beep_comes_last() {
+        let sink = MockSink::new(true);
+        let (builder, _) = mock_builder(&sink);
+        let player = builder.roger_beep(Some(RogerBeep::default())).build().unwrap();
+        let beeps = |player: &Player| player.lock_queued().entries.iter()
+            .map(|entry| (matches!(entry.queued, Queued::RogerBeep), entry.dropped.load(Ordering::SeqCst)))
+            .collect::<Vec<_>>();
+
+        player.queue_cw("K", 20, CW_TONE_HZ).unwrap();
+        assert_eq!(beeps(&player), [(false, false), (true, false)]);
+        // Only the one after the last of the audio is sent
+        player.queue_dtmf("1", Duration::from_millis(20), Duration::ZERO).unwrap();
+        assert_eq!(beeps(&player), [(false, false), (true, true), (false, false), (true, false)]);
+    }
+
+    #[test]
+    fn a_dry_run_never_keys_the_radio() {
+        let sink = MockSink::new(true);
+        let (builder, line) = mock_builder(&sink);
+        let player = builder.dry_run(true).build().unwrap();
+        player.queue_cw("TEST", 20, CW_TONE_HZ).unwrap();
+
+        player.play().unwrap();
+        wait_for_state(&player, PlayerState::Transmitting);
+        assert!(!*line.lock().unwrap());
+        assert!(player.ptt_is_enabled().unwrap());
+        sink.let_go();
+        player.wait_until_empty().unwrap();
+        assert_eq!(player.duty_cycle(Duration::from_secs(60)), 0.0);
+
+        // Turning it on mid-transmission lets go of the radio
+        player.set_dry_run(false).unwrap();
+        player.set_ptt(true).unwrap();
+        assert!(*line.lock().unwrap());
+        player.set_dry_run(true).unwrap();
+        assert!(!*line.lock().unwrap());
+        assert!(player.ptt_is_enabled().unwrap());
+    }
+
+    #[test]
+    fn a_test_key_keeps_to_the_limits() {
+        let sink = MockSink::new(true);
+        let (builder, line) = mock_builder(&sink);
+        let player = builder.max_transmit(Some(Duration::from_secs(5))).build().unwrap();
+        // Which the lead delay counts towards
+        assert!(matches!(player.test_key(Duration::from_secs(5)),
+                         Err(PlayerError::TuneTooLong{limit, ..}) if limit == Duration::from_millis(4990)));
+        assert!(matches!(player.tune(Duration::from_secs(5)), Err(PlayerError::TuneTooLong{..})));
+
+        player.queue_cw("K", 20, CW_TONE_HZ).unwrap();
+        assert!(matches!(player.test_key(Duration::from_millis(50)), Err(PlayerError::AudioQueued)));
+        player.play().unwrap();
+        wait_for_state(&player, PlayerState::Transmitting);
+        assert!(matches!(player.test_key(Duration::from_millis(50)), Err(PlayerError::AlreadyTransmitting)));
+        // Nothing's left queued by a carrier that can't go out either
+        assert!(matches!(player.tune(Duration::from_millis(50)), Err(PlayerError::AlreadyTransmitting)));
+        assert_eq!(sink.len(), 1);
+        sink.let_go();
+        player.wait_until_empty().unwrap();
+        assert!(!*line.lock().unwrap());
+    }
+
+    #[test]
+    fn a_test_key_sends_no_audio() {
+        let sink = MockSink::new(false);
+        let (builder, line) = mock_builder(&sink);
+        let (events, received) = mpsc::channel();
+        let player = Arc::new(builder.events(events).build().unwrap());
+
+        let keying = Arc::clone(&player);
+        let test_key = thread::spawn(move || keying.test_key(Duration::from_millis(300)));
+        thread::sleep(Duration::from_millis(100));
+        assert!(*line.lock().unwrap());
+        test_key.join().unwrap().unwrap();
+        assert!(!*line.lock().unwrap());
+
+        assert_eq!(received.try_iter().collect::<Vec<_>>(), [PlayerEvent::Keyed, PlayerEvent::TransmitEnded]);
+        assert_eq!(sink.played(), 0);
+        assert_eq!(sink.len(), 0);
+    }
+
+    #[test]
+    fn a_test_key_can_be_stopped() {
+        let sink = MockSink::new(false);
+        let (builder, line) = mock_builder(&sink);
+        let player = Arc::new(builder.build().unwrap());
+
+        let keying = Arc::clone(&player);
+        let test_key = thread::spawn(move || keying.test_key(Duration::from_secs(20)));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(player.state().unwrap(), PlayerState::Keyed);
+        let stopping = Instant::now();
+        player.stop().unwrap();
+        assert!(stopping.elapsed() < Duration::from_secs(1));
+        assert!(!*line.lock().unwrap());
+        test_key.join().unwrap().unwrap();
+
+        // Paused, too
+        let keying = Arc::clone(&player);
+        let test_key = thread::spawn(move || keying.test_key(Duration::from_secs(20)));
+        thread::sleep(Duration::from_millis(100));
+        player.pause().unwrap();
+        test_key.join().unwrap().unwrap();
+        assert!(!*line.lock().unwrap());
+    }
+
+    #[test]
+    fn a_test_key_keeps_to_the_duty_cycle() {
+        let sink = MockSink::new(false);
+        let (builder, _) = mock_builder(&sink);
+        let limit = DutyCycleLimit{window: Duration::from_secs(10), max: 0.1};
+        let player = builder.duty_cycle_limit(Some(limit)).build().unwrap();
+        assert!(matches!(player.test_key(Duration::from_secs(2)), Err(PlayerError::DutyCycleExceeded{..})));
+        player.test_key(Duration::from_millis(50)).unwrap();
+    }
+
+    #[test]
+    fn a_test_key_tries_again_to_release() {
+        let sink = MockSink::new(false);
+        let line = Arc::new(Mutex::new(false));
+        let player = Player::builder()
+            .ptt_control(Box::new(StuckPtt{line: Arc::clone(&line), refusals: 2}))
+            .audio_sink(Arc::clone(&sink) as Arc<dyn AudioSink>)
+            .build().unwrap();
+        player.test_key(Duration::from_millis(50)).unwrap();
+        assert!(!*line.lock().unwrap());
+
+        // Past the point of giving up, the error comes back to us
+        let player = Player::builder()
+            .ptt_control(Box::new(StuckPtt{line: Arc::clone(&line), refusals: TEST_KEY_RELEASE_ATTEMPTS + 1}))
+            .audio_sink(sink as Arc<dyn AudioSink>)
+            .build().unwrap();
+        assert!(matches!(player.test_key(Duration::from_millis(50)), Err(PlayerError::Ptt(_))));
+        assert!(*line.lock().unwrap());
+    }
+
+    #[test]
+    fn audio_can_be_queued_from_memory() {
+        let sink = MockSink::new(true);
+        let (player, _) = mock_player(&sink);
+
+        player.queue_bytes(wav(800).into_inner(), None).unwrap();
+        player.queue_bytes(wav(800).into_inner(), Some(AudioFormat::Wav)).unwrap();
+        assert_eq!(player.queue_len(), 2);
+
+        assert!(matches!(player.queue_bytes(wav(800).into_inner(), Some(AudioFormat::Flac)), Err(PlayerError::DecodeStream(_))));
+        assert!(matches!(player.queue_bytes(b"not audio".to_vec(), None), Err(PlayerError::DecodeStream(_))));
+        assert_eq!(player.queue_len(), 2);
+    }
+
+    #[test]
+    fn the_queue_says_what_is_in_it() {
+        let path = std::env::temp_dir().join(format!("rplayer-queue-items-{}.wav", std::process::id()));
+        std::fs::write(&path, wav(8000).into_inner()).unwrap();
+        let path = path.to_string_lossy().into_owned();
+        let sink = MockSink::new(true);
+        let (player, _) = mock_player(&sink);
+        assert_eq!(player.current_item(), None);
+
+        player.queue_audio_labelled(path.clone(), "the weather".to_string()).unwrap();
+        player.queue_many(std::slice::from_ref(&path)).unwrap();
+        player.queue_cw("K", 20, CW_TONE_HZ).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let current = player.current_item().unwrap();
+        assert_eq!(current.path.as_deref(), Some(path.as_str()));
+        assert_eq!(current.label.as_deref(), Some("the weather"));
+        assert_eq!(current.duration, Some(Duration::from_secs(1)));
+        let remaining:Vec<_> = player.remaining().into_iter().map(|item| item.description).collect();
+        assert_eq!(remaining, [format!("'{}'", path), "the Morse code 'K'".to_string()]);
+
+        sink.let_go();
+        player.play().unwrap();
+        player.wait_until_empty().unwrap();
+        assert_eq!(player.current_item(), None);
+        assert!(player.remaining().is_empty());
+    }
+
+    #[test]
+    fn audio_duration_is_known_before_playing() {
+        let path = std::env::temp_dir().join(format!("rplayer-duration-{}.wav", std::process::id()));
+        std::fs::write(&path, wav(12000).into_inner()).unwrap();
+        let duration = Player::audio_duration(&path.to_string_lossy());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(duration.unwrap(), Some(Duration::from_millis(1500)));
+
+        assert!(matches!(Player::audio_duration("/nonexistent/rplayer.wav"), Err(PlayerError::FileOpen{..})));
+    }
+
+    #[test]
+    fn every_over_goes_in_the_transmission_log() {
+        let path = std::env::temp_dir().join(format!("rplayer-player-log-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let sink = MockSink::new(false);
+        let (builder, _) = mock_builder(&sink);
+        let player = builder.transmission_log(path.clone()).build().unwrap();
+
+        player.transmit_tone(1000.0, Duration::from_millis(50), 0.5).unwrap();
+        player.transmit_dtmf("1", Duration::from_millis(50), Duration::ZERO).unwrap();
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let overs:Vec<_> = log.lines().collect();
+        assert_eq!(overs.len(), 2);
+        assert!(overs[0].contains("\"audio\":[\"a 1000Hz tone\"]"), "{}", overs[0]);
+        assert!(overs[1].contains("\"audio\":[\"the DTMF digits '1'\"]"), "{}", overs[1]);
+    }
+
+    #[test]
+    fn pausing_releases_ptt_and_keeps_the_audio() {
+        let sink = MockSink::new(true);
+        let (player, line) = mock_player(&sink);
+        player.queue_cw("TEST", 20, CW_TONE_HZ).unwrap();
+
+        player.play().unwrap();
+        wait_for_state(&player, PlayerState::Transmitting);
+        assert!(*line.lock().unwrap());
+
+        player.pause().unwrap();
+        wait_for_state(&player, PlayerState::Paused);
+        assert!(!*line.lock().unwrap());
+        assert_eq!(player.queue_len(), 1);
+        assert!(matches!(player.pause(), Err(PlayerError::AlreadyPaused)));
+
+        // And it picks up again from there
+        sink.let_go();
+        player.play().unwrap();
+        wait_for_state(&player, PlayerState::Idle);
+        assert!(!*line.lock().unwrap());
+    }
+
+    #[test]
+    fn position_leaves_out_the_lead_delay_and_pauses() {
+        let sink = MockSink::new(true);
+        let (builder, _) = mock_builder(&sink);
+        let player = builder.lead_delay(Duration::from_millis(100)).build().unwrap();
+        assert_eq!(player.position(), None);
+
+        player.queue_dtmf("1", Duration::from_secs(1), Duration::from_secs(1)).unwrap();
+        assert_eq!(player.position(), Some(Duration::ZERO));
+        assert_eq!(player.progress(), Some(0.0));
+
+        player.play().unwrap();
+        wait_for_state(&player, PlayerState::Transmitting);
+        thread::sleep(Duration::from_millis(50));
+        let position = player.position().unwrap();
+        assert!(position >= Duration::from_millis(50) && position < Duration::from_millis(150), "{:?}", position);
+        assert!(player.progress().is_some_and(|progress| progress > 0.0 && progress < 0.1));
+
+        player.pause().unwrap();
+        wait_for_state(&player, PlayerState::Paused);
+        let paused_at = player.position().unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(player.position(), Some(paused_at));
+
+        player.stop().unwrap();
+        assert_eq!(player.position(), None);
+        assert_eq!(player.progress(), None);
+    }
+
+    #[test]
+    fn seeking_keeps_the_radio_keyed() {
+        let sink = MockSink::new(true);
+        let (player, line) = mock_player(&sink);
+        player.queue_cw("TEST", 20, CW_TONE_HZ).unwrap();
+        player.play().unwrap();
+        wait_for_state(&player, PlayerState::Transmitting);
+
+        match player.seek(Duration::from_secs(1)) {
+            Err(PlayerError::SeekUnsupported{playing, source_type}) => {
+                assert_eq!(playing, "the Morse code 'TEST'");
+                assert_eq!(source_type, "MockSink");
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(*line.lock().unwrap());
+        assert_eq!(player.state().unwrap(), PlayerState::Transmitting);
+        player.stop().unwrap();
+    }
+
+    #[test]
+    fn stopwatch_follows_seeks() {
+        let mut played = Stopwatch{elapsed: Duration::from_secs(10), since: None};
+        played.skip(Duration::from_secs(2), Duration::from_secs(5));
+        assert_eq!(played.read(), Duration::from_secs(13));
+        played.skip(Duration::from_secs(5), Duration::ZERO);
+        assert_eq!(played.read(), Duration::from_secs(8));
+        played.skip(Duration::from_secs(20), Duration::ZERO);
+        assert_eq!(played.read(), Duration::ZERO);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_transmissions_can_be_awaited() {
+        let sink = MockSink::new(false);
+        let (player, line) = mock_player(&sink);
+        let player = crate::async_player::AsyncPlayer::new(player);
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+
+        runtime.block_on(async {
+            player.player().queue_cw("TEST", 20, CW_TONE_HZ).unwrap();
+            player.play().await.unwrap();
+            player.wait_until_empty().await.unwrap();
+            assert_eq!(player.state().await.unwrap(), PlayerState::Idle);
+        });
+        assert!(!*line.lock().unwrap());
+    }
+
+    #[test]
+    fn playing_in_the_tail_carries_straight_on() {
+        let sink = MockSink::new(false);
+        let (builder, line) = mock_builder(&sink);
+        let (events, received) = mpsc::channel();
+        let player = builder.tail_delay(Duration::from_millis(500)).hang_time(Duration::from_millis(500))
+            .events(events).build().unwrap();
+
+        player.queue_dtmf("1", Duration::from_millis(20), Duration::ZERO).unwrap();
+        player.play().unwrap();
+        while received.recv_timeout(Duration::from_secs(5)).unwrap() != PlayerEvent::QueueDrained {}
+        player.queue_dtmf("2", Duration::from_millis(20), Duration::ZERO).unwrap();
+        let played_at = Instant::now();
+        player.play().unwrap();
+        assert!(played_at.elapsed() < Duration::from_millis(250));
+        assert_eq!(received.recv_timeout(Duration::from_millis(250)), Ok(PlayerEvent::TransmitStarted));
+        assert!(*line.lock().unwrap());
+
+        player.stop().unwrap();
+        let events:Vec<_> = received.try_iter().collect();
+        assert!(!events.contains(&PlayerEvent::Keyed), "{:?}", events);
+    }
+
+    #[test]
+    fn the_ctcss_tone_goes_out_for_as_long_as_the_radio_is_keyed() {
+        let (sink, tone_sink) = (MockSink::new(false), MockSink::real_time());
+        let (builder, line) = mock_builder(&sink);
+        let (events, received) = mpsc::channel();
+        let player = builder.tone_sink(Arc::clone(&tone_sink) as Arc<dyn AudioSink>).ctcss(Some(100.0))
+            .lead_delay(Duration::from_millis(300)).hang_time(Duration::from_millis(300))
+            .events(events).build().unwrap();
+
+        player.queue_dtmf("1", Duration::from_millis(20), Duration::ZERO).unwrap();
+        player.play().unwrap();
+        // Well inside the lead delay, with none of the audio out yet
+        thread::sleep(Duration::from_millis(150));
+        assert_eq!(sink.played(), 0);
+        assert!((tone_sink.peak() - CTCSS_LEVEL).abs() < 0.01, "{}", tone_sink.peak());
+
+        // Still going in the hang time, after the audio
+        while received.recv_timeout(Duration::from_secs(5)).unwrap() != PlayerEvent::QueueDrained {}
+        assert!(*line.lock().unwrap());
+        assert_eq!(tone_sink.len(), 1);
+
+        while received.recv_timeout(Duration::from_secs(5)).unwrap() != PlayerEvent::TransmitEnded {}
+        assert!(!*line.lock().unwrap());
+        assert_eq!(tone_sink.len(), 0);
+    }
+
+    #[test]
+    fn playing_within_the_hang_time_stays_keyed() {
+        let sink = MockSink::new(false);
+        let (builder, line) = mock_builder(&sink);
+        let (events, received) = mpsc::channel();
+        let player = builder.hang_time(Duration::from_millis(300)).events(events).build().unwrap();
+        let digit = || player.queue_dtmf("1", Duration::from_millis(20), Duration::ZERO).unwrap();
+        let mut seen = Vec::new();
+        let mut wait_for_drain = || loop {
+            let event = received.recv_timeout(Duration::from_secs(5)).unwrap();
+            seen.push(event.clone());
+            if event == PlayerEvent::QueueDrained {
+                break;
+            }
+        };
+
+        digit();
+        player.play().unwrap();
+        wait_for_drain();
+        wait_for_state(&player, PlayerState::Keyed);
+        assert!(*line.lock().unwrap());
+
+        digit();
+        player.play().unwrap();
+        wait_for_drain();
+        player.wait_until_empty().unwrap();
+        assert!(!*line.lock().unwrap());
+
+        let events:Vec<_> = seen.into_iter().chain(received.try_iter()).collect();
+        assert_eq!(events.iter().filter(|&event| *event == PlayerEvent::Keyed).count(), 1, "{:?}", events);
+        assert_eq!(events.iter().filter(|&event| *event == PlayerEvent::TransmitStarted).count(), 2, "{:?}", events);
+        assert_eq!(events.last(), Some(&PlayerEvent::TransmitEnded));
+
+        // pause() cuts the hang time short
+        digit();
+        player.play().unwrap();
+        while received.recv_timeout(Duration::from_secs(5)).unwrap() != PlayerEvent::QueueDrained {}
+        wait_for_state(&player, PlayerState::Keyed);
+        let paused_at = Instant::now();
+        player.pause().unwrap();
+        wait_for_state(&player, PlayerState::Idle);
+        assert!(paused_at.elapsed() < Duration::from_millis(250));
+        assert!(!*line.lock().unwrap());
+    }
+
+    #[test]
+    fn keyed_time_is_what_falls_in_the_window() {
+        let now = Instant::now();
+        let ago = |secs| now - Duration::from_secs(secs);
+        let mut periods = KeyedPeriods::default();
+        periods.keyed(ago(100));
+        periods.released(ago(90));
+        periods.keyed(ago(30));
+        periods.keyed(ago(25));
+        periods.released(ago(20));
+        periods.keyed(ago(5));
+
+        assert_eq!(periods.periods.len(), 3);
+        assert_eq!(periods.keyed_time(Duration::from_secs(10), now), Duration::from_secs(5));
+        assert_eq!(periods.keyed_time(Duration::from_secs(28), now), Duration::from_secs(13));
+        assert_eq!(periods.keyed_time(Duration::from_secs(95), now), Duration::from_secs(20));
+    }
+
+    #[test]
+    fn play_keeps_to_the_duty_cycle() {
+        let sink = MockSink::new(false);
+        let (builder, _) = mock_builder(&sink);
+        let limit = DutyCycleLimit{window: Duration::from_secs(2), max: 0.5};
+        let player = builder.duty_cycle_limit(Some(limit)).build().unwrap();
+
+        // The mock plays the tone straight away, so it's only keyed for the delays and a look or two
+        player.transmit_tone(1000.0, Duration::from_millis(200), 0.5).unwrap();
+        let duty = player.duty_cycle(Duration::from_secs(2));
+        assert!(duty > 0.0 && duty < 0.25, "{}", duty);
+
+        // A second's worth on top of that would be over the limit
+        player.queue_dtmf("1", Duration::from_secs(1), Duration::ZERO).unwrap();
+        assert!(matches!(player.play(), Err(PlayerError::DutyCycleExceeded{..})));
+        assert_eq!(player.state().unwrap(), PlayerState::Paused);
+    }
+
+    #[test]
+    fn dropping_the_player_releases_ptt() {
+        let sink = MockSink::new(true);
+        let (player, line) = mock_player(&sink);
+        player.queue_cw("TEST", 20, CW_TONE_HZ).unwrap();
+        player.play().unwrap();
+        wait_for_state(&player, PlayerState::Transmitting);
+
+        drop(player);
+        assert!(!*line.lock().unwrap());
+    }
+
+    #[test]
+    fn concurrent_play_and_pause_dont_leave_the_radio_keyed() {
+        let sink = MockSink::new(true);
+        let (player, line) = mock_player(&sink);
+        let player = Arc::new(player);
+        player.queue_cw("TEST", 20, CW_TONE_HZ).unwrap();
+
+        let threads:Vec<_> = (0..4).map(|i| {
+            let player = Arc::clone(&player);
+            thread::spawn(move || {
+                for _ in 0..10 {
+                    // Whichever gets there first wins, the others are told off for it
+                    let result = if i % 2 == 0 { player.play() } else { player.pause() };
+                    assert!(matches!(result, Ok(()) | Err(PlayerError::AlreadyTransmitting | PlayerError::AlreadyPaused)));
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        player.stop().unwrap();
+        assert!(!*line.lock().unwrap());
+        assert_eq!(player.state().unwrap(), PlayerState::Idle);
+    }
+
+    #[test]
+    fn fades_default_to_a_tenth_of_the_delays() {
+        let sink = MockSink::new(false);
+        let (builder, _) = mock_builder(&sink);
+        let mut player = builder.lead_delay(Duration::from_millis(200)).tail_delay(Duration::from_secs(2)).build().unwrap();
+        assert_eq!(player.fades(), (Duration::from_millis(20), MAX_DEFAULT_FADE));
+
+        player.set_fades(Duration::ZERO, Duration::from_millis(100));
+        assert_eq!(player.fades(), (Duration::ZERO, Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn dtmf_is_queued_only_if_every_digit_can_be_sent() {
+        let sink = MockSink::new(true);
+        let (player, _) = mock_player(&sink);
+        let (tone, gap) = (Duration::from_millis(100), Duration::from_millis(50));
+
+        assert!(matches!(player.queue_dtmf("12X", tone, gap), Err(PlayerError::Dtmf(_))));
+        assert_eq!(player.queue_len(), 0);
+        player.queue_dtmf("*73#", tone, gap).unwrap();
+        assert_eq!(player.queue_len(), 1);
+        assert_eq!(sink.len(), 1);
+    }
+
+    #[test]
+    fn the_roger_beep_only_follows_the_last_of_the_audio() {
+        let sink = MockSink::new(true);
+        let (builder, _) = mock_builder(&sink);
+        let player = builder.roger_beep(Some(RogerBeep::default())).build().unwrap();
+
+        player.queue_reader(wav(100)).unwrap();
+        player.queue_reader(wav(100)).unwrap();
+        sink.let_go();
+        player.play().unwrap();
+        player.wait_until_empty().unwrap();
+        assert_eq!(sink.played(), 200 + roger_beep_samples());
+    }
+
+    #[test]
+    fn pausing_sends_the_roger_beep() {
+        let (sink, courtesy_sink) = (MockSink::new(true), MockSink::new(false));
+        let (builder, line) = mock_builder(&sink);
+        let player = builder
+            .courtesy_sink(Arc::clone(&courtesy_sink) as Arc<dyn AudioSink>)
+            .roger_beep(Some(RogerBeep::default()))
+            .build()
+            .unwrap();
+        player.queue_cw("TEST", 20, CW_TONE_HZ).unwrap();
+
+        player.play().unwrap();
+        wait_for_state(&player, PlayerState::Transmitting);
+        player.pause().unwrap();
+        wait_for_state(&player, PlayerState::Paused);
+        assert_eq!(courtesy_sink.played(), roger_beep_samples());
+        assert!(!*line.lock().unwrap());
+    }
+
+    #[test]
+    fn audio_devices_are_picked_out_by_name_or_pattern() {
+        let names:Vec<String> = ["default", "sysdefault:CARD=Device", "front:CARD=Device,DEV=0", "front:CARD=PCH,DEV=0"]
+            .map(String::from).to_vec();
+        let choose = |device: AudioDevice| device.choose(&names);
+
+        assert_eq!(choose(AudioDevice::Name("front:CARD=Device,DEV=0".to_string())).unwrap(), 2);
+        assert_eq!(choose(AudioDevice::Name("pch".to_string())).unwrap(), 3);
+        assert!(matches!(choose(AudioDevice::Name("CARD=Device".to_string())), Err(PlayerError::AmbiguousDevice{..})));
+
+        assert_eq!(choose(AudioDevice::Pattern(Regex::new("^front:CARD=Device,").unwrap())).unwrap(), 2);
+        match choose(AudioDevice::Pattern(Regex::new("CARD=Digirig").unwrap())) {
+            Err(PlayerError::DeviceNotFound{available, ..}) => assert_eq!(available, names),
+            other => panic!("{:?}", other.map(|i| &names[i])),
+        }
+        match choose(AudioDevice::Pattern(Regex::new("CARD=Device").unwrap())) {
+            Err(PlayerError::AmbiguousDevice{matches, ..}) => assert_eq!(matches, names[1..3]),
+            other => panic!("{:?}", other.map(|i| &names[i])),
+        }
+    }
+
+    #[test]
+    fn volume_is_clamped() {
+        assert_eq!(clamp_volume(0.5, 1.0), 0.5);
+        assert_eq!(clamp_volume(1.5, 1.0), 1.0);
+        assert_eq!(clamp_volume(-0.5, 1.0), 0.0);
+        assert_eq!(clamp_volume(f32::INFINITY, MAX_VOLUME), MAX_VOLUME);
+        assert_eq!(clamp_volume(f32::NAN, 1.0), 0.0);
+    }
+
+    #[test]
+    fn tones_are_checked() {
+        assert!(check_tone(1000.0, 0.5).is_ok());
+        assert!(check_tone(1000.0, 1.0).is_ok());
+        assert!(matches!(check_tone(0.0, 0.5), Err(PlayerError::InvalidFrequency(_))));
+        assert!(matches!(check_tone(f32::NAN, 0.5), Err(PlayerError::InvalidFrequency(_))));
+        assert!(matches!(check_tone(1000.0, 1.1), Err(PlayerError::InvalidAmplitude(_))));
+        assert!(matches!(check_tone(1000.0, f32::NAN), Err(PlayerError::InvalidAmplitude(_))));
+    }
+
+    #[test]
+    fn auto_id_is_sent_first_and_then_once_per_interval() {
+        let config = AutoIdConfig{callsign: "N0CALL".to_string(), interval: Duration::from_secs(600), wpm: 20};
+        let auto_id = Arc::new(AutoId::new(config).unwrap());
+
+        let mut first = IdSlot{auto_id: Arc::clone(&auto_id), id: None};
+        assert_eq!(first.by_ref().count(), auto_id.samples.len());
+        // Not long enough since, so the next slot is left empty
+        let mut second = IdSlot{auto_id: Arc::clone(&auto_id), id: None};
+        assert_eq!(second.next(), None);
+
+        *auto_id.last_sent.lock().unwrap() = Some(Instant::now() - Duration::from_secs(600));
+        let mut third = IdSlot{auto_id: Arc::clone(&auto_id), id: None};
+        assert!(third.next().is_some());
+    }
+
+    #[test]
+    fn audio_is_repeated_at_least_once() {
+        let sink = MockSink::new(true);
+        let (player, _) = mock_player(&sink);
+        // Turned down before the file's even looked at
+        let queued = player.queue_audio_repeated("/no/such/file.wav".to_string(), 0, Duration::ZERO, Repeats::KeyedThrough);
+        assert!(matches!(queued, Err(PlayerError::NoRepeats)));
+        assert_eq!(player.queue_len(), 0);
+    }
+
+    #[test]
+    fn auto_id_callsign_is_checked() {
+        let config = AutoIdConfig{callsign: "N0CALL~".to_string(), interval: Duration::from_secs(600), wpm: 20};
+        assert!(matches!(AutoId::new(config), Err(PlayerError::Cw(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tty_errors_keep_their_errno() {
+        use std::os::fd::IntoRawFd;
+        use nix::errno::Errno;
+
+        let sink = MockSink::new(false);
+        let opened = Player::builder()
+            .audio_sink(Arc::clone(&sink) as Arc<dyn AudioSink>)
+            .tty_path("/dev/no-such-tty".to_string())
+            .build();
+        assert!(matches!(opened, Err(PlayerError::TtyOpen{source: Errno::ENOENT, ..})), "{:?}", opened.err());
+
+        // A pty has no modem control lines, so releasing PTT on startup fails
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let built = Player::builder()
+            .audio_sink(sink as Arc<dyn AudioSink>)
+            .ptt_control(Box::new(ptt::TtyPtt::with_fd(pty.slave.into_raw_fd(), PttLine::Rts)))
+            .build();
+        match built {
+            Err(PlayerError::Ioctl{what, source: Errno::ENOTTY}) => assert_eq!(what, "Failed to get tty parameters"),
+            other => panic!("{:?}", other.err()),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::ffi::CString;
+use anyhow::{anyhow, Context, Result};
+use hidapi::{HidApi, HidDevice};
+use super::PttControl;
+
+// C-Media's USB vendor ID and the product ID of the CM108 itself. Most CM108/CM119 based
+// interfaces enumerate with these, though some are rebadged with their own IDs.
+pub const CM108_VENDOR_ID:u16 = 0x0d8c;
+pub const CM108_PRODUCT_ID:u16 = 0x000c;
+
+// Nearly every CM108 interface (DMK URI, most Baofeng cables, ...) wires PTT to GPIO3
+pub const CM108_DEFAULT_GPIO:u8 = 3;
+
+// Which HID device the CM108 is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Cm108Device {
+    // A hidraw device node, e.g. /dev/hidraw0
+    Path(String),
+    UsbId{vendor_id: u16, product_id: u16},
+}
+
+impl Default for Cm108Device {
+    fn default() -> Cm108Device {
+        Cm108Device::UsbId{vendor_id: CM108_VENDOR_ID, product_id: CM108_PRODUCT_ID}
+    }
+}
+
+// The HID output report that drives one GPIO pin: a report ID, a reserved byte, the mask of pins
+// to drive, the levels to drive them to, and another reserved byte
+fn gpio_report(gpio: u8, asserted: bool) -> [u8; 5] {
+    let mask = 1 << (gpio - 1);
+    [0, 0, mask, if asserted { mask } else { 0 }, 0]
+}
+
+// Keys the radio with a GPIO pin of a CM108/CM119 USB sound card, by writing HID output reports
+pub struct Cm108Ptt {
+    device: Option<HidDevice>,
+    gpio: u8,
+    asserted: bool,
+}
+
+impl Cm108Ptt {
+    pub fn open(device: &Cm108Device, gpio: u8) -> Result<Cm108Ptt> {
+        if !(1..=8).contains(&gpio) {
+            return Err(anyhow!("CM108 GPIO{} doesn't exist, only GPIO1 to GPIO8 do", gpio));
+        }
+
+        let api = HidApi::new().context("Failed to initialize hidapi")?;
+        let device = match device {
+            Cm108Device::Path(path) => api.open_path(&CString::new(path.as_str())?)
+                .with_context(|| format!("Failed to open HID device '{}'", path))?,
+            Cm108Device::UsbId{vendor_id, product_id} => api.open(*vendor_id, *product_id)
+                .with_context(|| format!("Failed to open HID device {:04x}:{:04x}", vendor_id, product_id))?,
+        };
+
+        Ok(Cm108Ptt{device: Some(device), gpio, asserted: false})
+    }
+}
+
+impl PttControl for Cm108Ptt {
+    fn set(&mut self, asserted: bool) -> Result<()> {
+        let device = self.device.as_ref().context("HID device is already closed")?;
+        device.write(&gpio_report(self.gpio, asserted))
+            .with_context(|| format!("Failed to set CM108 GPIO{}", self.gpio))?;
+
+        self.asserted = asserted;
+        Ok(())
+    }
+
+    // The GPIO pins are outputs, so report what the pin was last set to
+    fn get(&mut self) -> Result<bool> {
+        Ok(self.asserted)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.device = None;
+        Ok(())
+    }
+}
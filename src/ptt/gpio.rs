@@ -0,0 +1,39 @@
+use anyhow::{Context, Result};
+use rppal::gpio::{Gpio, OutputPin};
+use super::PttControl;
+
+// Keys the radio with a Raspberry Pi GPIO pin, typically driving a transistor across the radio's
+// PTT contacts. 'active_high' says which level keys the transmitter.
+pub struct GpioPtt {
+    pin: OutputPin,
+    active_high: bool,
+}
+
+impl GpioPtt {
+    pub fn open(bcm_pin: u8, active_high: bool) -> Result<GpioPtt> {
+        let gpio = Gpio::new().context("Failed to access GPIO, is this running on a Raspberry Pi?")?;
+        let pin = gpio.get(bcm_pin)
+            .with_context(|| format!("Failed to claim GPIO{}", bcm_pin))?;
+
+        // Claim the pin at its inactive level so that the radio doesn't key momentarily
+        let pin = if active_high { pin.into_output_low() } else { pin.into_output_high() };
+
+        Ok(GpioPtt{pin, active_high})
+    }
+}
+
+impl PttControl for GpioPtt {
+    fn set(&mut self, asserted: bool) -> Result<()> {
+        if asserted == self.active_high {
+            self.pin.set_high();
+        } else {
+            self.pin.set_low();
+        }
+
+        Ok(())
+    }
+
+    fn get(&mut self) -> Result<bool> {
+        Ok(self.pin.is_set_high() == self.active_high)
+    }
+}
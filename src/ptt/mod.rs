@@ -0,0 +1,109 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+#[cfg(feature = "cm108")]
+mod cm108;
+#[cfg(feature = "gpio")]
+mod gpio;
+mod null;
+mod rigctld;
+mod serial;
+#[cfg(unix)]
+mod tty;
+
+#[cfg(feature = "cm108")]
+pub use cm108::{Cm108Device, Cm108Ptt, CM108_DEFAULT_GPIO, CM108_PRODUCT_ID, CM108_VENDOR_ID};
+#[cfg(feature = "gpio")]
+pub use gpio::GpioPtt;
+pub use null::NullPtt;
+pub use rigctld::{RigctldPtt, RIGCTLD_DEFAULT_PORT};
+pub use serial::{list_serial_ports, SerialPortListing, SerialPtt};
+#[cfg(unix)]
+pub use tty::TtyPtt;
+
+// A modem control line that we can drive
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModemLine {
+    #[default]
+    Rts,
+    Dtr,
+}
+
+// The modem control line that keys the transmitter. Most interfaces (including the Digirig) use
+// RTS, but some older homebrew cables and adapters wire the PTT transistor to DTR instead.
+pub type PttLine = ModemLine;
+
+// The state of every modem control line of a serial port, for diagnostics (is the radio reporting
+// a carrier? is CTS asserted?). 'true' means the line is asserted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ModemStatus {
+    pub rts: bool,
+    pub dtr: bool,
+    pub cts: bool,
+    pub dsr: bool,
+    pub cd: bool,
+    pub ri: bool,
+}
+
+// A way of keying the radio's transmitter. Implementations only deal with the electrical state of
+// their keying line; the Player takes care of inverted polarity on top of that.
+pub trait PttControl: Send {
+    fn set(&mut self, asserted: bool) -> Result<()>;
+
+    fn get(&mut self) -> Result<bool>;
+
+    fn assert(&mut self) -> Result<()> {
+        self.set(true)
+    }
+
+    fn deassert(&mut self) -> Result<()> {
+        self.set(false)
+    }
+
+    fn is_asserted(&mut self) -> Result<bool> {
+        self.get()
+    }
+
+    // Whether the radio is reporting a carrier (i.e. its squelch is open). Many interfaces wire
+    // the squelch output to the DCD line of the serial port, but not every backend can see it.
+    fn carrier_detect(&mut self) -> Result<bool> {
+        Err(anyhow!("This PTT backend can't detect a carrier"))
+    }
+
+    // Drives any of the modem control lines, not just the one that keys the radio, e.g. for a
+    // cable that keys on DTR but needs RTS held up to power its level converter
+    fn set_line(&mut self, _line: ModemLine, _asserted: bool) -> Result<()> {
+        Err(no_modem_lines())
+    }
+
+    fn get_line(&mut self, _line: ModemLine) -> Result<bool> {
+        Err(no_modem_lines())
+    }
+
+    fn modem_status(&mut self) -> Result<ModemStatus> {
+        Err(no_modem_lines())
+    }
+
+    // Releases the underlying device. Nothing else is called on the backend afterwards, so this
+    // is where errors that would otherwise be lost in Drop can be reported.
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn no_modem_lines() -> anyhow::Error {
+    anyhow!("This PTT backend has no modem control lines")
+}
+
+// Opens the modem control line of a serial device. The raw ioctls are the most direct way of doing
+// this on Unix; everywhere else goes through the serialport crate.
+#[cfg(unix)]
+pub fn open_line(tty_path: &str, line: PttLine) -> Result<Box<dyn PttControl>> {
+    Ok(Box::new(TtyPtt::open(tty_path, line)?))
+}
+
+#[cfg(not(unix))]
+pub fn open_line(tty_path: &str, line: PttLine) -> Result<Box<dyn PttControl>> {
+    Ok(Box::new(SerialPtt::open(tty_path, line)?))
+}
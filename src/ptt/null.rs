@@ -0,0 +1,26 @@
+use anyhow::Result;
+use super::PttControl;
+
+// Keys nothing at all, for when the radio is keyed some other way (e.g. by VOX) or there's no
+// radio. It remembers what it was last set to, so the Player still sees the line it expects.
+#[derive(Debug, Default)]
+pub struct NullPtt {
+    asserted: bool,
+}
+
+impl NullPtt {
+    pub fn new() -> NullPtt {
+        NullPtt::default()
+    }
+}
+
+impl PttControl for NullPtt {
+    fn set(&mut self, asserted: bool) -> Result<()> {
+        self.asserted = asserted;
+        Ok(())
+    }
+
+    fn get(&mut self) -> Result<bool> {
+        Ok(self.asserted)
+    }
+}
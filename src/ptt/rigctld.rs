@@ -0,0 +1,78 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use super::PttControl;
+
+// The port rigctld listens on unless told otherwise
+pub const RIGCTLD_DEFAULT_PORT:u16 = 4532;
+
+// Don't let a wedged rigctld hang the Player (and leave the radio keyed) forever
+const RIGCTLD_TIMEOUT:Duration = Duration::from_secs(2);
+
+// Keys the radio over CAT, by asking a Hamlib rigctld daemon to switch the rig between transmit
+// and receive. One connection is kept open and reused for every transmission.
+pub struct RigctldPtt {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl RigctldPtt {
+    // 'address' is a host:port, e.g. "localhost:4532"
+    pub fn connect(address: &str) -> Result<RigctldPtt> {
+        let writer = TcpStream::connect(address)
+            .with_context(|| format!("Failed to connect to rigctld at '{}'", address))?;
+        writer.set_read_timeout(Some(RIGCTLD_TIMEOUT))?;
+        writer.set_write_timeout(Some(RIGCTLD_TIMEOUT))?;
+        let reader = BufReader::new(writer.try_clone()?);
+
+        Ok(RigctldPtt{reader, writer})
+    }
+
+    fn command(&mut self, command: &str) -> Result<String> {
+        writeln!(self.writer, "{}", command)
+            .with_context(|| format!("Failed to send '{}' to rigctld", command))?;
+
+        let mut response = String::new();
+        self.reader.read_line(&mut response)
+            .with_context(|| format!("Failed to read rigctld response to '{}'", command))?;
+        if response.is_empty() {
+            return Err(anyhow!("rigctld closed the connection"));
+        }
+
+        Ok(response.trim().to_string())
+    }
+}
+
+// rigctld answers set commands (and failed get commands) with "RPRT <code>", where 0 is success
+// and anything else is a negated Hamlib error code
+fn check_report(command: &str, response: &str) -> Result<()> {
+    match response.strip_prefix("RPRT ").map(str::parse::<i32>) {
+        Some(Ok(0)) => Ok(()),
+        Some(Ok(code)) => Err(anyhow!("rigctld failed to run '{}' (Hamlib error {})", command, code)),
+        _ => Err(anyhow!("Unexpected rigctld response to '{}': '{}'", command, response)),
+    }
+}
+
+impl PttControl for RigctldPtt {
+    fn set(&mut self, asserted: bool) -> Result<()> {
+        let command = if asserted { "T 1" } else { "T 0" };
+        let response = self.command(command)?;
+
+        check_report(command, &response)
+    }
+
+    fn get(&mut self) -> Result<bool> {
+        let response = self.command("t")?;
+        match response.as_str() {
+            "0" => Ok(false),
+            // Hamlib reports which kind of PTT is active (mic, data, ...) as 1 to 3
+            "1" | "2" | "3" => Ok(true),
+            // Failures come back as a report code rather than a PTT state
+            _ => {
+                check_report("t", &response)?;
+                Err(anyhow!("Unexpected rigctld response to 't': '{}'", response))
+            },
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{Context, Result};
+use log::debug;
+use serialport::{SerialPort, SerialPortType, UsbPortInfo};
+use super::{ModemLine, PttControl, PttLine};
+
+// A serial port that could be used for PTT
+#[derive(Clone, Debug)]
+pub struct SerialPortListing {
+    pub path: String,
+    // Vendor/product IDs and descriptor strings of USB adapters, to tell e.g. a Digirig apart
+    pub usb: Option<UsbPortInfo>,
+    // Whether some process already has the port open, where that can be found out. Only root can
+    // see other users' processes, so this is a best guess otherwise.
+    pub in_use: Option<bool>,
+}
+
+// Lists candidate serial ports for PTT, so that users don't have to guess at /dev/ttyUSB0
+pub fn list_serial_ports() -> Result<Vec<SerialPortListing>> {
+    let mut ports:Vec<SerialPortListing> = serialport::available_ports()
+        .context("Failed to enumerate serial ports")?
+        .into_iter()
+        .map(|info| SerialPortListing{
+            path: info.port_name,
+            usb: match info.port_type {
+                SerialPortType::UsbPort(usb) => Some(usb),
+                _ => None,
+            },
+            in_use: None,
+        })
+        .collect();
+
+    // Also pick up USB serial device nodes that serialport doesn't recognise from sysfs
+    #[cfg(target_os = "linux")]
+    if let Ok(entries) = std::fs::read_dir("/dev") {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = format!("/dev/{}", name);
+            if (name.starts_with("ttyUSB") || name.starts_with("ttyACM")) && !ports.iter().any(|p| p.path == path) {
+                ports.push(SerialPortListing{path, usb: None, in_use: None});
+            }
+        }
+    }
+
+    ports.sort_by(|a, b| a.path.cmp(&b.path));
+    for port in &mut ports {
+        port.in_use = port_in_use(&port.path);
+    }
+
+    Ok(ports)
+}
+
+// Looks through every process's open files for the port
+#[cfg(target_os = "linux")]
+fn port_in_use(path: &str) -> Option<bool> {
+    let port = std::fs::canonicalize(path).ok()?;
+    for process in std::fs::read_dir("/proc").ok()?.flatten() {
+        let Ok(fds) = std::fs::read_dir(process.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            if std::fs::read_link(fd.path()).is_ok_and(|target| target == port) {
+                return Some(true);
+            }
+        }
+    }
+
+    Some(false)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn port_in_use(_path: &str) -> Option<bool> {
+    None
+}
+
+// Keys the radio with a modem control line through the serialport crate. This works everywhere
+// serialport does, including macOS and Windows.
+pub struct SerialPtt {
+    port: Option<Box<dyn SerialPort>>,
+    line: PttLine,
+    // serialport can only read back the input lines, so these are what the outputs were last set to
+    rts: bool,
+    dtr: bool,
+}
+
+impl SerialPtt {
+    pub fn open(tty_path: &str, line: PttLine) -> Result<SerialPtt> {
+        // The baud rate doesn't matter, nothing is ever sent over the port
+        let port = serialport::new(tty_path, 9600)
+            .open()
+            .with_context(|| format!("Failed to open serial port '{}'", tty_path))?;
+
+        Ok(SerialPtt{port: Some(port), line, rts: false, dtr: false})
+    }
+}
+
+impl PttControl for SerialPtt {
+    fn set(&mut self, asserted: bool) -> Result<()> {
+        self.set_line(self.line, asserted)
+    }
+
+    fn get(&mut self) -> Result<bool> {
+        self.get_line(self.line)
+    }
+
+    fn set_line(&mut self, line: ModemLine, asserted: bool) -> Result<()> {
+        let port = self.port.as_mut().context("Serial port is already closed")?;
+        match line {
+            ModemLine::Rts => port.write_request_to_send(asserted),
+            ModemLine::Dtr => port.write_data_terminal_ready(asserted),
+        }.context("Failed to set serial port control line")?;
+        debug!("{:?} {}", line, if asserted { "asserted" } else { "deasserted" });
+
+        match line {
+            ModemLine::Rts => self.rts = asserted,
+            ModemLine::Dtr => self.dtr = asserted,
+        }
+        Ok(())
+    }
+
+    fn get_line(&mut self, line: ModemLine) -> Result<bool> {
+        Ok(match line {
+            ModemLine::Rts => self.rts,
+            ModemLine::Dtr => self.dtr,
+        })
+    }
+
+    fn carrier_detect(&mut self) -> Result<bool> {
+        let port = self.port.as_mut().context("Serial port is already closed")?;
+        port.read_carrier_detect().context("Failed to read serial port carrier detect")
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.port = None;
+        Ok(())
+    }
+}
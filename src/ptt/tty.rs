@@ -0,0 +1,402 @@
+use std::os::fd::RawFd;
+use std::thread;
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, error, info, warn};
+use nix::errno::Errno;
+use nix::{fcntl, ioctl_read_bad, libc};
+use super::{ModemLine, ModemStatus, PttControl, PttLine};
+
+// The ioctl request numbers and modem line bits differ between platforms (e.g. Linux and the BSDs
+// don't agree on TIOCMGET), so they all come from libc rather than being hardcoded
+
+fn line_flag(line: ModemLine) -> i32 {
+    match line {
+        ModemLine::Rts => libc::TIOCM_RTS,
+        ModemLine::Dtr => libc::TIOCM_DTR,
+    }
+}
+
+// The modem control bits with one line asserted or deasserted, leaving every other line alone
+fn with_line(control_bits: i32, line: ModemLine, asserted: bool) -> i32 {
+    if asserted {
+        control_bits | line_flag(line)
+    } else {
+        control_bits & !line_flag(line)
+    }
+}
+
+fn modem_status(control_bits: i32) -> ModemStatus {
+    let is_set = |flag| (control_bits & flag) != 0;
+    ModemStatus{
+        rts: is_set(libc::TIOCM_RTS),
+        dtr: is_set(libc::TIOCM_DTR),
+        cts: is_set(libc::TIOCM_CTS),
+        dsr: is_set(libc::TIOCM_DSR),
+        cd: is_set(libc::TIOCM_CAR),
+        ri: is_set(libc::TIOCM_RNG),
+    }
+}
+
+// How many times to try reopening a TTY that's gone away, waiting RECONNECT_BACKOFF (unless the
+// tests say otherwise) before the first attempt and twice as long again before each one after
+// that (3.1s in all)
+const RECONNECT_ATTEMPTS:u32 = 5;
+const RECONNECT_BACKOFF:Duration = Duration::from_millis(100);
+
+// What the ioctls fail with once a USB serial adapter has been unplugged (or has reset itself):
+// the fd stays open but the device behind it is gone for good
+fn device_is_gone(e: Errno) -> bool {
+    matches!(e, Errno::EIO | Errno::ENODEV | Errno::ENXIO)
+}
+
+fn open_tty(tty_path: &str) -> nix::Result<RawFd> {
+    fcntl::open(tty_path, fcntl::OFlag::O_RDWR, nix::sys::stat::Mode::S_IRWXU)
+}
+
+// Everything a TtyPtt asks of the kernel, so that the tests can stand a fake serial port in for it
+#[derive(Clone, Copy)]
+struct Syscalls {
+    open: fn(&str) -> nix::Result<RawFd>,
+    close: fn(RawFd) -> nix::Result<()>,
+    get_bits: fn(RawFd) -> nix::Result<i32>,
+    set_bits: fn(RawFd, i32) -> nix::Result<()>,
+}
+
+const SYSCALLS:Syscalls = Syscalls{open: open_tty, close: nix::unistd::close, get_bits, set_bits};
+
+// Keys the radio with a modem control line of a TTY by driving the TIOCMGET/TIOCMSET ioctls
+// directly
+pub struct TtyPtt {
+    // None once the device has gone away and couldn't be reopened, or after it's been closed
+    fd: Option<RawFd>,
+    line: PttLine,
+    closed: bool,
+    // Where to reopen the TTY from if it goes away. None if it was handed to us already open.
+    tty_path: Option<String>,
+    // How long to wait before the first attempt at reopening it, doubling after each one
+    reconnect_backoff: Duration,
+    sys: Syscalls,
+}
+
+impl TtyPtt {
+    // We need the *_bad variants here because these are "old"-style syscalls
+    ioctl_read_bad!(tiocmget, libc::TIOCMGET, i32);
+    ioctl_read_bad!(tiocmset, libc::TIOCMSET, i32);
+
+    pub fn open(tty_path: &str, line: PttLine) -> Result<TtyPtt> {
+        let fd = (SYSCALLS.open)(tty_path).context("Failed to open TTY device")?;
+
+        Ok(TtyPtt{fd: Some(fd), line, closed: false, tty_path: Some(tty_path.to_string()),
+                   reconnect_backoff: RECONNECT_BACKOFF, sys: SYSCALLS})
+    }
+
+    // Takes over a TTY that's already open, e.g. one that was opened with different flags or
+    // handed down by a parent process. It's closed along with the TtyPtt. There's no path to
+    // reopen it from, so a TtyPtt made this way can't reconnect.
+    pub fn with_fd(fd: RawFd, line: PttLine) -> TtyPtt {
+        TtyPtt{fd: Some(fd), line, closed: false, tty_path: None, reconnect_backoff: RECONNECT_BACKOFF, sys: SYSCALLS}
+    }
+
+    // Closes the TTY and opens it again from the same path, retrying with backoff while the
+    // device is still missing, then deasserts the PTT line. Opening a TTY raises RTS and DTR on
+    // Linux, so without that a radio keyed on either would start transmitting the moment its
+    // adapter came back. It's left unkeyed even if it was keyed before: whatever was being sent
+    // has been cut off, and the next set(true) keys up again. If it can't be reopened, the TTY is
+    // left closed and the next ioctl tries again.
+    //
+    // This happens by itself when an ioctl fails because the device has gone away. A USB adapter
+    // can come back under a different /dev/ttyUSBn, so it's worth using its /dev/serial/by-id path.
+    pub fn reconnect(&mut self) -> Result<()> {
+        let tty_path = self.tty_path.clone()
+            .ok_or_else(|| anyhow!("Can't reopen a TTY that was handed over already open"))?;
+        if let Some(fd) = self.fd.take() {
+            // The device is gone, so there's nothing to be done about this failing
+            let _ = (self.sys.close)(fd);
+        }
+        self.closed = false;
+
+        let mut backoff = self.reconnect_backoff;
+        let mut last_error = anyhow!("never tried");
+        for attempt in 1..=RECONNECT_ATTEMPTS {
+            thread::sleep(backoff);
+            backoff *= 2;
+
+            let fd = match (self.sys.open)(&tty_path) {
+                Ok(fd) => fd,
+                Err(e) => {
+                    warn!("Failed to reopen {} (attempt {} of {}): {}", tty_path, attempt, RECONNECT_ATTEMPTS, e);
+                    last_error = anyhow::Error::new(e).context("Failed to open TTY device");
+                    continue;
+                }
+            };
+            // The adapter can show up a little before it'll answer ioctls
+            let restored = (self.sys.get_bits)(fd).and_then(|bits| (self.sys.set_bits)(fd, with_line(bits, self.line, false)));
+            match restored {
+                Ok(()) => {
+                    info!("Reopened {} with {:?} deasserted", tty_path, self.line);
+                    self.fd = Some(fd);
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!("Reopened {} but couldn't set {:?} (attempt {} of {}): {}", tty_path, self.line, attempt, RECONNECT_ATTEMPTS, e);
+                    let _ = (self.sys.close)(fd);
+                    last_error = anyhow::Error::new(e).context("Failed to set tty parameters");
+                }
+            }
+        }
+        Err(last_error.context(format!("Gave up reopening {} after {} attempts", tty_path, RECONNECT_ATTEMPTS)))
+    }
+
+    // Runs an ioctl, reconnecting and running it again if the device has gone away. A TTY that
+    // couldn't be reopened last time counts as gone.
+    fn ioctl<T>(&mut self, what: &'static str, op: impl Fn(RawFd) -> nix::Result<T>) -> Result<T> {
+        if self.closed {
+            bail!("{}: the TTY device is closed", what);
+        }
+        match self.fd.map_or(Err(Errno::ENODEV), &op) {
+            Err(e) if device_is_gone(e) && self.tty_path.is_some() => {
+                warn!("Lost the TTY device ({}), reconnecting", e);
+                self.reconnect().with_context(|| format!("{}: {}", what, e))?;
+                self.fd.map_or(Err(Errno::ENODEV), &op).context(what)
+            }
+            result => result.context(what),
+        }
+    }
+
+    fn control_bits(&mut self) -> Result<i32> {
+        self.ioctl("Failed to get tty parameters", self.sys.get_bits)
+    }
+}
+
+fn get_bits(fd: RawFd) -> nix::Result<i32> {
+    let mut control_bits:i32 = 0;
+    unsafe { TtyPtt::tiocmget(fd, &mut control_bits) }?;
+    Ok(control_bits)
+}
+
+fn set_bits(fd: RawFd, mut control_bits: i32) -> nix::Result<()> {
+    unsafe { TtyPtt::tiocmset(fd, &mut control_bits) }?;
+    Ok(())
+}
+
+impl PttControl for TtyPtt {
+    fn set(&mut self, asserted: bool) -> Result<()> {
+        self.set_line(self.line, asserted)
+    }
+
+    fn get(&mut self) -> Result<bool> {
+        self.get_line(self.line)
+    }
+
+    // Read-modify-write so that only the given line is touched and the result doesn't depend on
+    // what state the line was in beforehand
+    fn set_line(&mut self, line: ModemLine, asserted: bool) -> Result<()> {
+        let control_bits = with_line(self.control_bits()?, line, asserted);
+
+        let set_bits = self.sys.set_bits;
+        self.ioctl("Failed to set tty parameters", |fd| set_bits(fd, control_bits))?;
+        debug!("{:?} {} (control bits now {:#x})", line, if asserted { "asserted" } else { "deasserted" }, control_bits);
+
+        Ok(())
+    }
+
+    fn get_line(&mut self, line: ModemLine) -> Result<bool> {
+        Ok((self.control_bits()? & line_flag(line)) != 0)
+    }
+
+    fn carrier_detect(&mut self) -> Result<bool> {
+        Ok((self.control_bits()? & libc::TIOCM_CAR) != 0)
+    }
+
+    fn modem_status(&mut self) -> Result<ModemStatus> {
+        Ok(modem_status(self.control_bits()?))
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.closed = true;
+        let Some(fd) = self.fd.take() else {
+            return Ok(());
+        };
+
+        // Because we have a raw FD from nix::fcntl, we need to explicitly close(2) it in order to
+        // not leak the FD
+        (self.sys.close)(fd).context("Failed to close TTY device")
+    }
+}
+
+impl Drop for TtyPtt {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            error!("{:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::os::fd::IntoRawFd;
+    use nix::pty::openpty;
+    use super::*;
+
+    // A serial port that's just its modem control bits, one per test thread
+    #[derive(Default)]
+    struct FakeTty {
+        bits: i32,
+        // What the next ioctl fails with, if anything
+        failing: Option<Errno>,
+        // Whether the device is there to be reopened
+        missing: bool,
+        opens: u32,
+    }
+
+    thread_local! {
+        static FAKE_TTY:RefCell<FakeTty> = RefCell::new(FakeTty::default());
+    }
+
+    const FAKE_FD:RawFd = 1000;
+
+    fn fake_ptt(line: PttLine, bits: i32) -> TtyPtt {
+        FAKE_TTY.set(FakeTty{bits, ..FakeTty::default()});
+        let sys = Syscalls{
+            // Like Linux, opening it raises RTS and DTR
+            open: |_| FAKE_TTY.with_borrow_mut(|tty| {
+                tty.opens += 1;
+                if tty.missing {
+                    return Err(Errno::ENOENT);
+                }
+                tty.bits |= libc::TIOCM_RTS | libc::TIOCM_DTR;
+                Ok(FAKE_FD)
+            }),
+            close: |_| Ok(()),
+            get_bits: |_| FAKE_TTY.with_borrow_mut(|tty| match tty.failing.take() {
+                Some(e) => Err(e),
+                None => Ok(tty.bits),
+            }),
+            set_bits: |_, bits| FAKE_TTY.with_borrow_mut(|tty| match tty.failing.take() {
+                Some(e) => Err(e),
+                None => {
+                    tty.bits = bits;
+                    Ok(())
+                }
+            }),
+        };
+        TtyPtt{fd: Some(FAKE_FD), line, closed: false, tty_path: Some("/dev/ttyFAKE".to_string()),
+                // No need to wait for a fake device to come back
+                reconnect_backoff: Duration::ZERO, sys}
+    }
+
+    fn fake_bits() -> i32 {
+        FAKE_TTY.with_borrow(|tty| tty.bits)
+    }
+
+    #[test]
+    fn keying_flips_just_the_ptt_line() {
+        let others = libc::TIOCM_DTR | libc::TIOCM_CAR;
+        let mut ptt = fake_ptt(PttLine::Rts, others);
+        ptt.set(true).unwrap();
+        assert_eq!(fake_bits(), others | libc::TIOCM_RTS);
+        assert!(ptt.get().unwrap());
+        ptt.set(false).unwrap();
+        assert_eq!(fake_bits(), others);
+        assert!(!ptt.get().unwrap());
+
+        let mut ptt = fake_ptt(PttLine::Dtr, libc::TIOCM_RTS);
+        ptt.set(true).unwrap();
+        assert_eq!(fake_bits(), libc::TIOCM_RTS | libc::TIOCM_DTR);
+        ptt.set_line(ModemLine::Rts, false).unwrap();
+        assert_eq!(fake_bits(), libc::TIOCM_DTR);
+        assert!(ptt.get().unwrap());
+
+        // A failed read leaves the line alone
+        FAKE_TTY.with_borrow_mut(|tty| tty.failing = Some(Errno::ENOTTY));
+        assert!(ptt.set(false).is_err());
+        assert_eq!(fake_bits(), libc::TIOCM_DTR);
+    }
+
+    #[test]
+    fn with_line_only_touches_that_line() {
+        let bits = libc::TIOCM_DTR | libc::TIOCM_CAR;
+        assert_eq!(with_line(bits, ModemLine::Rts, true), bits | libc::TIOCM_RTS);
+        assert_eq!(with_line(bits, ModemLine::Dtr, false), libc::TIOCM_CAR);
+        assert_eq!(with_line(bits, ModemLine::Rts, false), bits);
+    }
+
+    #[test]
+    fn modem_status_decodes_every_line() {
+        let status = modem_status(libc::TIOCM_RTS | libc::TIOCM_CTS | libc::TIOCM_CAR);
+        assert_eq!(status, ModemStatus{rts: true, cts: true, cd: true, ..ModemStatus::default()});
+        assert_eq!(modem_status(0), ModemStatus::default());
+    }
+
+    // Pseudo-terminals have no modem control lines (Linux answers TIOCMGET with ENOTTY), so an fd
+    // that isn't a real serial port has to come back as an error rather than as "not keyed"
+    #[test]
+    fn pty_without_modem_lines_reports_errors() {
+        let pty = openpty(None, None).unwrap();
+        let mut ptt = TtyPtt::with_fd(pty.slave.into_raw_fd(), PttLine::Rts);
+
+        let error = ptt.set(true).unwrap_err();
+        assert!(error.to_string().contains("Failed to get tty parameters"), "{}", error);
+        assert!(ptt.get().is_err());
+        assert!(ptt.modem_status().is_err());
+    }
+
+    #[test]
+    fn reconnecting_leaves_the_radio_unkeyed() {
+        let mut ptt = fake_ptt(PttLine::Rts, 0);
+        ptt.set(true).unwrap();
+
+        // Unplugged and plugged back in while keyed
+        FAKE_TTY.with_borrow_mut(|tty| tty.failing = Some(Errno::ENODEV));
+        assert!(!ptt.get().unwrap());
+        assert_eq!(fake_bits(), libc::TIOCM_DTR);
+        ptt.set(true).unwrap();
+        assert_eq!(fake_bits(), libc::TIOCM_DTR | libc::TIOCM_RTS);
+    }
+
+    #[test]
+    fn a_failed_reopen_is_tried_again_next_time() {
+        let mut ptt = fake_ptt(PttLine::Rts, 0);
+        FAKE_TTY.with_borrow_mut(|tty| {
+            tty.failing = Some(Errno::ENODEV);
+            tty.missing = true;
+        });
+        let e = ptt.set(true).unwrap_err();
+        assert_eq!(e.downcast_ref::<Errno>(), Some(&Errno::ENOENT));
+        assert_eq!(ptt.fd, None);
+        assert_eq!(FAKE_TTY.with_borrow(|tty| tty.opens), RECONNECT_ATTEMPTS);
+
+        FAKE_TTY.with_borrow_mut(|tty| tty.missing = false);
+        ptt.set(true).unwrap();
+        assert_eq!(ptt.fd, Some(FAKE_FD));
+        assert_eq!(fake_bits(), libc::TIOCM_DTR | libc::TIOCM_RTS);
+
+        // Closing it for good stops it coming back
+        ptt.close().unwrap();
+        assert!(ptt.get().is_err());
+        assert_eq!(FAKE_TTY.with_borrow(|tty| tty.opens), RECONNECT_ATTEMPTS + 1);
+    }
+
+    #[test]
+    fn only_a_missing_device_is_reconnected() {
+        assert!(device_is_gone(Errno::EIO));
+        assert!(device_is_gone(Errno::ENODEV));
+        assert!(!device_is_gone(Errno::ENOTTY));
+
+        // Handed over already open, so there's nowhere to reopen it from
+        let pty = openpty(None, None).unwrap();
+        let mut ptt = TtyPtt::with_fd(pty.slave.into_raw_fd(), PttLine::Rts);
+        assert!(ptt.reconnect().is_err());
+    }
+
+    #[test]
+    fn close_is_idempotent() {
+        let pty = openpty(None, None).unwrap();
+        let mut ptt = TtyPtt::with_fd(pty.slave.into_raw_fd(), PttLine::Rts);
+
+        ptt.close().unwrap();
+        ptt.close().unwrap();
+    }
+}
@@ -0,0 +1,470 @@
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, Context, Result};
+use log::{error, info, warn};
+use crate::player::{Player, PlayerState};
+
+// Cron expressions are never searched further ahead than this. It's long enough for anything that
+// can match at all (February 29th on a Monday only comes round every 28 years or so), so an
+// expression that doesn't match within it never will, e.g. "0 0 30 2 *".
+const CRON_SEARCH_DAYS:u64 = 40 * 366;
+
+const SECS_PER_MINUTE:u64 = 60;
+const SECS_PER_DAY:u64 = 24 * 60 * SECS_PER_MINUTE;
+
+// When a job goes out
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum When {
+    // Every so often, starting as soon as the scheduler does
+    Every(Duration),
+    // At every minute that matches a cron expression, see Cron
+    Cron(Cron),
+}
+
+// A file to transmit on a schedule, no more than 'max_per_hour' times in any hour if that's given
+// (e.g. so that a beacon on a tight interval can't hog a shared channel)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Job {
+    pub path: String,
+    pub when: When,
+    pub max_per_hour: Option<u32>,
+}
+
+impl Job {
+    pub fn every(path: String, interval: Duration) -> Job {
+        Job{path, when: When::Every(interval), max_per_hour: None}
+    }
+
+    pub fn cron(path: String, expression: &str) -> Result<Job> {
+        Ok(Job{path, when: When::Cron(Cron::parse(expression)?), max_per_hour: None})
+    }
+
+    pub fn max_per_hour(mut self, limit: Option<u32>) -> Job {
+        self.max_per_hour = limit;
+        self
+    }
+}
+
+// The usual five field cron expression: minute, hour, day of the month, month and day of the week
+// (0 or 7 is Sunday), each of which is '*', a number, a range like '1-5', or a list of those, and
+// any of them can take a step, e.g. '*/10' or '0-30/15'. Like cron, a job that gives both a day of
+// the month and a day of the week runs on either, unless one of them starts with '*' (even with a
+// step, like '*/2'), in which case it has to match both. Times are in UTC, like the rest of the
+// logs on the air, and there's no daylight saving to fall foul of.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Cron> {
+        let fields:Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(anyhow!("'{}' should have 5 fields (minute, hour, day, month and weekday), not {}",
+                               expression, fields.len()));
+        };
+
+        let parse = || -> Result<Cron> {
+            let weekday_set = parse_field(weekdays, 0..=7, "weekday")?;
+            Ok(Cron{
+                minutes: parse_field(minutes, 0..=59, "minute")?,
+                hours: parse_field(hours, 0..=23, "hour")?,
+                days: parse_field(days, 1..=31, "day")?,
+                months: parse_field(months, 1..=12, "month")?,
+                // Sunday is both 0 and 7
+                weekdays: (weekday_set | weekday_set >> 7) & 0x7f,
+                any_day: days.starts_with('*'),
+                any_weekday: weekdays.starts_with('*'),
+            })
+        };
+        parse().with_context(|| format!("Invalid cron expression '{}'", expression))
+    }
+
+    // The first whole minute that matches strictly after 'after', both in seconds since the Unix
+    // epoch, or None if there isn't one (within CRON_SEARCH_DAYS)
+    pub fn next_after(&self, after: u64) -> Option<u64> {
+        let mut minute = after / SECS_PER_MINUTE + 1;
+        let last = minute + CRON_SEARCH_DAYS * SECS_PER_DAY / SECS_PER_MINUTE;
+        while minute < last {
+            let secs = minute * SECS_PER_MINUTE;
+            let days = secs / SECS_PER_DAY;
+            if !self.matches_day(days) {
+                // Nothing else today can match either
+                minute = (days + 1) * SECS_PER_DAY / SECS_PER_MINUTE;
+                continue;
+            }
+            let minute_of_day = secs % SECS_PER_DAY / SECS_PER_MINUTE;
+            if bit(self.hours, minute_of_day / 60) && bit(self.minutes, minute_of_day % 60) {
+                return Some(secs);
+            }
+            minute += 1;
+        }
+        None
+    }
+
+    fn matches_day(&self, days_since_epoch: u64) -> bool {
+        let (_, month, day) = civil_from_days(days_since_epoch);
+        // The epoch was a Thursday
+        let weekday = (days_since_epoch + 4) % 7;
+        if !bit(self.months, month) {
+            return false;
+        }
+        let day_matches = bit(self.days, day);
+        let weekday_matches = bit(self.weekdays, weekday);
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day_matches || weekday_matches,
+            _ => day_matches && weekday_matches,
+        }
+    }
+}
+
+fn bit(set: u64, n: u64) -> bool {
+    set & (1 << n) != 0
+}
+
+// One field of a cron expression, as a set of bits numbered by value
+fn parse_field(field: &str, range: RangeInclusive<u64>, name: &str) -> Result<u64> {
+    let number = |s: &str| -> Result<u64> {
+        let n = s.parse().map_err(|_| anyhow!("'{}' isn't a {}", s, name))?;
+        if !range.contains(&n) {
+            return Err(anyhow!("{} {} isn't between {} and {}", name, n, range.start(), range.end()));
+        }
+        Ok(n)
+    };
+
+    let mut set = 0;
+    for item in field.split(',') {
+        let (values, step) = match item.split_once('/') {
+            Some((values, step)) => match step.parse::<u64>() {
+                Ok(step) if step > 0 => (values, step),
+                _ => return Err(anyhow!("'{}' isn't a step for the {}", step, name)),
+            },
+            None => (item, 1),
+        };
+        let (first, last) = match values {
+            "*" => (*range.start(), *range.end()),
+            _ => match values.split_once('-') {
+                Some((first, last)) => (number(first)?, number(last)?),
+                // Like cron, "5/15" means from 5 to the end in 15s
+                None if step > 1 => (number(values)?, *range.end()),
+                None => (number(values)?, number(values)?),
+            },
+        };
+        if first > last {
+            return Err(anyhow!("The {} range {} is backwards", name, values));
+        }
+        for n in (first..=last).step_by(step as usize) {
+            set |= 1 << n;
+        }
+    }
+    Ok(set)
+}
+
+// The (year, month, day) of a number of days since the Unix epoch, from Howard Hinnant's
+// chrono-compatible date algorithms
+pub(crate) fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months counted from March, so that February's leap day comes last
+    let march_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * march_month + 2) / 5 + 1;
+    let month = if march_month < 10 { march_month + 3 } else { march_month - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+// A job and when it's next due
+struct Scheduled {
+    job: Job,
+    next: Instant,
+    // When in the last hour it went out, oldest first, for 'max_per_hour'
+    sent: VecDeque<Instant>,
+}
+
+impl Scheduled {
+    // Works out when the job is next due after 'now', which is the time it was last due
+    fn reschedule(&mut self, now: Instant) -> Result<()> {
+        self.next = match &self.job.when {
+            // Keep to the original schedule rather than drifting by however late we were, unless
+            // we've fallen so far behind that there's no catching up
+            When::Every(interval) => match self.next + *interval {
+                next if next > now => next,
+                _ => now + *interval,
+            },
+            When::Cron(cron) => next_cron(cron, now)?,
+        };
+        Ok(())
+    }
+
+    // Whether 'max_per_hour' leaves room for it to go out again at 'now'
+    fn allowed(&mut self, now: Instant) -> bool {
+        while self.sent.front().is_some_and(|&sent| now.duration_since(sent) >= Duration::from_secs(3600)) {
+            self.sent.pop_front();
+        }
+        self.job.max_per_hour.is_none_or(|limit| self.sent.len() < limit as usize)
+    }
+}
+
+// The Instant of the next time that 'cron' matches the system clock after 'now'
+fn next_cron(cron: &Cron, now: Instant) -> Result<Instant> {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let next = cron.next_after(since_epoch.as_secs())
+        .ok_or_else(|| anyhow!("The cron expression never matches"))?;
+    Ok(now + Duration::from_secs(next).saturating_sub(since_epoch))
+}
+
+// Drives a Player to transmit each of its jobs when they're due, e.g. a beacon every 10 minutes
+// and an ID on the hour. Jobs that come due together go out together, as one transmission. Any
+// that come due while the Player is doing something else (still transmitting the last job, or
+// something queued from elsewhere) are skipped until they're next due, rather than going out late
+// and off their schedule.
+pub struct Scheduler {
+    player: Arc<Player>,
+    jobs: Vec<Scheduled>,
+    clear_timeout: Option<Duration>,
+}
+
+impl Scheduler {
+    // Fails if any of the jobs would never be due
+    pub fn new(player: Arc<Player>, jobs: Vec<Job>) -> Result<Scheduler> {
+        let now = Instant::now();
+        let jobs = jobs.into_iter().map(|job| {
+            let next = match &job.when {
+                When::Every(interval) if interval.is_zero() => {
+                    return Err(anyhow!("The interval for '{}' must be more than 0", job.path));
+                }
+                When::Every(_) => now,
+                When::Cron(cron) => next_cron(cron, now).with_context(|| format!("'{}' would never be sent", job.path))?,
+            };
+            Ok(Scheduled{job, next, sent: VecDeque::new()})
+        }).collect::<Result<Vec<_>>>()?;
+
+        Ok(Scheduler{player, jobs, clear_timeout: None})
+    }
+
+    // Waits up to 'timeout' for the channel to be clear before each job goes out, see
+    // Player::play_when_clear(), and skips it if it doesn't clear in time. Without this, jobs go
+    // out as soon as they're due, unless the Player has been told to require a clear channel.
+    pub fn wait_for_clear(mut self, timeout: Option<Duration>) -> Scheduler {
+        self.clear_timeout = timeout;
+        self
+    }
+
+    // Transmits the jobs as they come due, forever (or until there's none left that can be)
+    pub fn run(mut self) {
+        if self.jobs.is_empty() {
+            warn!("Nothing to schedule");
+            return;
+        }
+        loop {
+            let Some(next) = self.send_due(Instant::now()) else {
+                error!("No jobs left to schedule");
+                return;
+            };
+            thread::sleep(next.saturating_duration_since(Instant::now()));
+        }
+    }
+
+    // Sends everything that's due at 'now' if the Player's free, or skips it if not, returning
+    // when to look again
+    fn send_due(&mut self, now: Instant) -> Option<Instant> {
+        if self.jobs.iter().any(|scheduled| scheduled.next <= now) {
+            match self.player.state() {
+                Ok(PlayerState::Idle) => self.send_jobs(now),
+                Ok(state) => self.skip_jobs(now, &format!("the player is busy ({})", state)),
+                Err(e) => self.skip_jobs(now, &format!("failed to get the player state: {:#}", e)),
+            }
+            self.reschedule(now);
+        }
+        self.jobs.iter().map(|scheduled| scheduled.next).min()
+    }
+
+    fn skip_jobs(&self, now: Instant, why: &str) {
+        for scheduled in self.jobs.iter().filter(|scheduled| scheduled.next <= now) {
+            warn!("Skipped scheduled '{}', {}", scheduled.job.path, why);
+        }
+    }
+
+    // Queues every job that's due as a single transmission
+    fn send_jobs(&mut self, now: Instant) {
+        let mut queued = Vec::new();
+        for (index, scheduled) in self.jobs.iter_mut().enumerate().filter(|(_, scheduled)| scheduled.next <= now) {
+            if !scheduled.allowed(now) {
+                info!("Skipping '{}', it's already been sent {} times in the last hour", scheduled.job.path, scheduled.sent.len());
+                continue;
+            }
+            let path = &scheduled.job.path;
+            match self.player.queue_audio(path.clone()) {
+                Ok(()) => {
+                    info!("Sending scheduled '{}'", path);
+                    queued.push(index);
+                }
+                Err(e) => error!("Failed to queue scheduled '{}': {:#}", path, e),
+            }
+        }
+
+        if !queued.is_empty() {
+            let played = match self.clear_timeout {
+                Some(timeout) => self.player.play_when_clear(timeout),
+                None => self.player.play(),
+            };
+            match played {
+                Ok(()) => queued.iter().for_each(|&index| self.jobs[index].sent.push_back(now)),
+                Err(e) => {
+                    error!("Failed to send the scheduled jobs: {:#}", e);
+                    // Don't leave them queued to go out with whatever's transmitted next
+                    self.player.clear_queue();
+                }
+            }
+        }
+    }
+
+    // Works out when every job that was due at 'now' is next due
+    fn reschedule(&mut self, now: Instant) {
+        self.jobs.retain_mut(|scheduled| scheduled.next > now || match scheduled.reschedule(now) {
+            Ok(()) => true,
+            Err(e) => {
+                // The clock must have jumped a very long way, so there's no telling when it's
+                // next due
+                error!("Failed to reschedule '{}', giving up on it: {:#}", scheduled.job.path, e);
+                false
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::tests as player_tests;
+
+    // 2001-09-09 01:46:00 UTC, a Sunday
+    const SUNDAY:u64 = 999_999_960;
+
+    fn minutes_after(expression: &str, after: u64) -> Option<u64> {
+        Cron::parse(expression).unwrap().next_after(after).map(|next| (next - after) / 60)
+    }
+
+    #[test]
+    fn days_since_the_epoch_are_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(SUNDAY / SECS_PER_DAY), (2001, 9, 9));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+    }
+
+    #[test]
+    fn fields_can_be_lists_ranges_and_steps() {
+        assert_eq!(parse_field("*", 0..=7, "weekday").unwrap(), 0xff);
+        assert_eq!(parse_field("1,3-5", 0..=59, "minute").unwrap(), 0b111010);
+        assert_eq!(parse_field("*/20", 0..=59, "minute").unwrap(), 1 | 1 << 20 | 1 << 40);
+        assert_eq!(parse_field("10/20", 0..=59, "minute").unwrap(), 1 << 10 | 1 << 30 | 1 << 50);
+    }
+
+    #[test]
+    fn bad_expressions_are_rejected() {
+        for expression in ["", "* * * *", "* * * * * *", "60 * * * *", "* * 0 * *", "5-1 * * * *",
+                           "*/0 * * * *", "x * * * *", "* * * 13 *", "* * * * 8"] {
+            assert!(Cron::parse(expression).is_err(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn next_is_the_first_matching_minute() {
+        // 01:46:40, so the next ten minute mark is 01:50
+        assert_eq!(minutes_after("*/10 * * * *", SUNDAY), Some(4));
+        assert_eq!(minutes_after("0 * * * *", SUNDAY), Some(14));
+        assert_eq!(minutes_after("* * * * *", SUNDAY), Some(1));
+        // Tomorrow's a Monday
+        assert_eq!(minutes_after("0 9 * * 1", SUNDAY), Some(14 + 22 * 60 + 9 * 60));
+        // Sunday is 0 and 7, and it's Sunday
+        assert_eq!(minutes_after("0 9 * * 7", SUNDAY), Some(14 + 7 * 60));
+    }
+
+    #[test]
+    fn day_and_weekday_match_either() {
+        // Not the 1st, but it is Sunday
+        assert_eq!(minutes_after("0 9 1 * 0", SUNDAY), Some(14 + 7 * 60));
+        // The 10th is a day earlier than the next Saturday
+        assert_eq!(minutes_after("0 0 10 * 6", SUNDAY), Some(14 + 22 * 60));
+    }
+
+    #[test]
+    fn a_stepped_wildcard_day_needs_the_weekday_too() {
+        // Today's the 9th, but not a Monday. Tomorrow's Monday the 10th, but that's even, so it's
+        // Monday the 17th.
+        assert_eq!(minutes_after("0 9 */2 * 1", SUNDAY), Some(14 + 7 * 60 + 8 * 24 * 60));
+    }
+
+    #[test]
+    fn impossible_dates_never_come() {
+        assert_eq!(minutes_after("0 0 30 2 *", SUNDAY), None);
+        assert!(minutes_after("0 0 29 2 *", SUNDAY).is_some());
+    }
+
+    #[test]
+    fn intervals_keep_to_their_schedule() {
+        let start = Instant::now();
+        let mut scheduled = Scheduled{job: Job::every("beacon.wav".to_string(), Duration::from_secs(600)), next: start, sent: VecDeque::new()};
+
+        // A little late doesn't push the rest back
+        scheduled.reschedule(start + Duration::from_secs(5)).unwrap();
+        assert_eq!(scheduled.next, start + Duration::from_secs(600));
+        // But if a whole interval has gone by, it starts again from now
+        scheduled.reschedule(start + Duration::from_secs(1500)).unwrap();
+        assert_eq!(scheduled.next, start + Duration::from_secs(2100));
+    }
+
+    #[test]
+    fn jobs_due_together_go_out_together() {
+        let path = std::env::temp_dir().join(format!("rplayer-scheduler-{}.wav", std::process::id()));
+        std::fs::write(&path, player_tests::wav(800).into_inner()).unwrap();
+        let path = path.to_string_lossy().into_owned();
+        let sink = player_tests::MockSink::new(true);
+        let (builder, _) = player_tests::mock_builder(&sink);
+        let player = Arc::new(builder.build().unwrap());
+
+        // A beacon every 10 minutes and an ID every hour both start straight away
+        let jobs = vec![Job::every(path.clone(), Duration::from_secs(600)), Job::every(path.clone(), Duration::from_secs(3600))];
+        let mut scheduler = Scheduler::new(Arc::clone(&player), jobs).unwrap();
+        let beacon_due = scheduler.jobs[0].next + Duration::from_secs(600);
+        let now = Instant::now();
+        assert_eq!(scheduler.send_due(now), Some(beacon_due));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(player.queue_len(), 2);
+        assert!(scheduler.jobs.iter().all(|scheduled| scheduled.sent.len() == 1));
+
+        // Whatever comes due while that's still going out is skipped until it's next due
+        let later = now + Duration::from_secs(600);
+        assert_eq!(scheduler.send_due(later), Some(beacon_due + Duration::from_secs(600)));
+        assert_eq!(scheduler.jobs[0].sent.len(), 1);
+        assert_eq!(player.queue_len(), 2);
+        sink.let_go();
+        player.wait_until_empty().unwrap();
+    }
+
+    #[test]
+    fn jobs_are_limited_per_hour() {
+        let start = Instant::now();
+        let job = Job::every("beacon.wav".to_string(), Duration::from_secs(60)).max_per_hour(Some(2));
+        let mut scheduled = Scheduled{job, next: start, sent: VecDeque::new()};
+
+        scheduled.sent.push_back(start);
+        scheduled.sent.push_back(start + Duration::from_secs(60));
+        assert!(!scheduled.allowed(start + Duration::from_secs(120)));
+        assert!(scheduled.allowed(start + Duration::from_secs(3600)));
+        assert_eq!(scheduled.sent.len(), 1);
+    }
+}
@@ -0,0 +1,128 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use crate::scheduler::civil_from_days;
+
+// A record of every over, for handing to whoever wants to know what went out and when. Unlike the
+// application log, this is one JSON object per line, appended as each over ends:
+//
+//   {"keyed":"2026-10-14T18:00:00.250Z","unkeyed":"2026-10-14T18:00:41.100Z","duration_secs":40.85,
+//    "audio":["'net.wav'","the roger beep"]}
+//
+// The times are when PTT was keyed and released (in UTC), however that came about, and 'audio' is
+// everything that made it to the air in between.
+pub struct TransmissionLog {
+    path: PathBuf,
+    file: File,
+    keyed: Option<(SystemTime, Instant)>,
+    aired: Arc<Mutex<Vec<String>>>,
+}
+
+#[derive(Serialize)]
+struct Over<'a> {
+    keyed: String,
+    unkeyed: String,
+    duration_secs: f64,
+    audio: &'a [String],
+}
+
+impl TransmissionLog {
+    // Appends to 'path', creating it if need be
+    pub fn open(path: &Path) -> Result<TransmissionLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .with_context(|| format!("Failed to open transmission log '{}'", path.display()))?;
+        Ok(TransmissionLog{path: path.to_path_buf(), file, keyed: None, aired: Arc::new(Mutex::new(Vec::new()))})
+    }
+
+    // Where the audio that's played says what it is as it starts
+    pub(crate) fn aired(&self) -> Arc<Mutex<Vec<String>>> {
+        Arc::clone(&self.aired)
+    }
+
+    fn lock_aired(&self) -> MutexGuard<'_, Vec<String>> {
+        self.aired.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn keyed(&mut self) {
+        // Keying again while keyed doesn't start a new over
+        if self.keyed.is_none() {
+            self.keyed = Some((SystemTime::now(), Instant::now()));
+            self.lock_aired().clear();
+        }
+    }
+
+    // Writes out the over that's just ended, if there was one
+    pub(crate) fn released(&mut self) -> Result<()> {
+        let Some((keyed, keyed_at)) = self.keyed.take() else {
+            return Ok(());
+        };
+        let duration = keyed_at.elapsed();
+        let audio = std::mem::take(&mut *self.lock_aired());
+
+        let over = Over{
+            keyed: format_utc(keyed),
+            unkeyed: format_utc(keyed + duration),
+            duration_secs: (duration.as_secs_f64() * 1000.0).round() / 1000.0,
+            audio: &audio,
+        };
+        let mut line = serde_json::to_string(&over)?;
+        line.push('\n');
+        // In one write, so that two Players sharing a log can't interleave their lines
+        self.file.write_all(line.as_bytes())
+            .and_then(|_| self.file.flush())
+            .with_context(|| format!("Failed to write to transmission log '{}'", self.path.display()))
+    }
+}
+
+// RFC 3339, to the millisecond
+fn format_utc(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days(secs / 86_400);
+    let secs_of_day = secs % 86_400;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day,
+            secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60, since_epoch.subsec_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use super::*;
+
+    #[test]
+    fn times_are_utc() {
+        assert_eq!(format_utc(UNIX_EPOCH + Duration::from_millis(1_000_000_000_250)), "2001-09-09T01:46:40.250Z");
+        assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn every_over_is_a_line() {
+        let path = env::temp_dir().join(format!("rplayer-transmission-log-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut log = TransmissionLog::open(&path).unwrap();
+
+        // Releasing without having keyed isn't an over
+        log.released().unwrap();
+        for audio in ["'one.wav'", "'two.wav'"] {
+            log.keyed();
+            log.aired().lock().unwrap().push(audio.to_string());
+            log.keyed();
+            log.released().unwrap();
+        }
+
+        let lines:Vec<serde_json::Value> = fs::read_to_string(&path).unwrap().lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["audio"], serde_json::json!(["'one.wav'"]));
+        assert_eq!(lines[1]["audio"], serde_json::json!(["'two.wav'"]));
+        assert!(lines[0]["duration_secs"].as_f64().unwrap() >= 0.0);
+        assert!(lines[0]["keyed"].as_str().unwrap().ends_with('Z'));
+    }
+}
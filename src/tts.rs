@@ -0,0 +1,117 @@
+use std::env;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+// Numbers the temporary files, so that two announcements being synthesized at once don't collide
+static SYNTHESIZED:AtomicU64 = AtomicU64::new(0);
+
+// An external text-to-speech engine, run once for every announcement. 'command' is the program
+// and its arguments, in which "{output}" is replaced with the path of the WAV file the engine
+// should write and "{text}" with the text to say. If none of the arguments take the text, it's
+// written to the engine's stdin instead, as piper expects. For example
+//
+//   ["espeak-ng", "-v", "en-gb", "-w", "{output}", "{text}"]
+//   ["piper", "--model", "en_GB-alan-medium.onnx", "--output_file", "{output}"]
+//
+// In a config file this is 'tts_command'.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct TtsEngine {
+    pub command: Vec<String>,
+}
+
+impl Default for TtsEngine {
+    fn default() -> TtsEngine {
+        TtsEngine{command: ["espeak-ng", "-w", "{output}", "{text}"].map(String::from).to_vec()}
+    }
+}
+
+// Removes the engine's output however synthesize() ends up returning
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // The engine may have failed before it ever wrote anything
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+impl TtsEngine {
+    pub fn new(command: Vec<String>) -> TtsEngine {
+        TtsEngine{command}
+    }
+
+    // Has the engine say 'text', returning the WAV file it wrote. That's read into memory, so the
+    // temporary file is already gone by the time this returns.
+    pub fn synthesize(&self, text: &str) -> Result<Vec<u8>> {
+        let (program, args) = self.command.split_first().ok_or_else(|| anyhow!("No text-to-speech command given"))?;
+        let output = TempFile(env::temp_dir().join(format!("rplayer-tts-{}-{}.wav",
+            std::process::id(), SYNTHESIZED.fetch_add(1, Ordering::Relaxed))));
+
+        let output_path = output.0.to_string_lossy();
+        let text_in_args = args.iter().any(|arg| arg.contains("{text}"));
+        let mut child = Command::new(program)
+            .args(args.iter().map(|arg| arg.replace("{output}", &output_path).replace("{text}", text)))
+            .stdin(if text_in_args { Stdio::null() } else { Stdio::piped() })
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                ErrorKind::NotFound => anyhow!("The text-to-speech engine '{}' isn't installed (or isn't on the PATH)", program),
+                _ => anyhow::Error::new(e).context(format!("Failed to run the text-to-speech engine '{}'", program)),
+            })?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())
+                .with_context(|| format!("Failed to send the text to '{}'", program))?;
+        }
+        let finished = child.wait_with_output()
+            .with_context(|| format!("Failed to wait for the text-to-speech engine '{}'", program))?;
+        if !finished.status.success() {
+            let stderr = String::from_utf8_lossy(&finished.stderr);
+            return Err(anyhow!("The text-to-speech engine '{}' failed ({}): {}", program, finished.status, stderr.trim()));
+        }
+
+        fs::read(&output.0)
+            .with_context(|| format!("The text-to-speech engine '{}' didn't write {}", program, output.0.display()))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> TtsEngine {
+        TtsEngine::new(["sh", "-c", script, "sh", "{output}", "{text}"].map(String::from).to_vec())
+    }
+
+    #[test]
+    fn the_output_is_read_back_and_removed() {
+        // The "WAV" is where it was written
+        let wav = sh(r#"printf '%s' "$1" > "$1""#).synthesize("hello").unwrap();
+        let path = PathBuf::from(String::from_utf8(wav).unwrap());
+        assert!(path.starts_with(env::temp_dir()));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn text_goes_to_stdin_if_no_argument_takes_it() {
+        let engine = TtsEngine::new(["sh", "-c", r#"cat > "$1""#, "sh", "{output}"].map(String::from).to_vec());
+        assert_eq!(engine.synthesize("on stdin").unwrap(), b"on stdin");
+    }
+
+    #[test]
+    fn engine_failures_are_errors() {
+        let e = sh("echo 'no voice' >&2; exit 3").synthesize("hello").unwrap_err();
+        assert!(format!("{:#}", e).contains("no voice"));
+
+        assert!(sh("true").synthesize("hello").is_err());
+        assert!(TtsEngine::new(vec!["rplayer-no-such-engine".to_string()]).synthesize("hello").is_err());
+        assert!(TtsEngine::new(Vec::new()).synthesize("hello").is_err());
+    }
+}